@@ -1,7 +1,8 @@
 use std::{fs, io, num};
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::BufRead;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 use nix::unistd;
 use strum_macros::Display;
 
@@ -10,19 +11,38 @@ pub enum CGroupError {
 	File(PathBuf, io::Error),
 	Parse(PathBuf, num::ParseIntError),
 	CgroupControllerNotFound(),
+	NotCgroupV2(PathBuf),
+	Populated(PathBuf),
+	NotVisible(String),
 }
 
 pub struct CGroupMemory {
 	pub usage: usize,
 	pub limit: usize,
 	pub unlimited: bool,
+	pub high: Option<usize>,
+	pub low: Option<usize>,
+	pub min: Option<usize>,
+	pub swap_usage: Option<usize>,
+	pub swap_limit: Option<usize>,
 }
 
-pub fn read_cgroup_memory() -> Result<CGroupMemory, CGroupError> {
-	if uses_cgroup_v2() {
-		read_cgroup_v2_memory()
+// the cgroup to compute the memory for
+#[derive(Debug, Clone)]
+pub enum CgroupTarget {
+	// the one memfill runs in
+	Own,
+	// any cgroup directory, either in the v2 hierarchy or in the v1 memory hierarchy
+	Path(PathBuf),
+	// the one another process runs in, e.g. the init process of a container
+	Pid(u32),
+}
+
+pub fn read_cgroup_memory(target: &CgroupTarget) -> Result<CGroupMemory, CGroupError> {
+	if is_cgroup_v2(target) {
+		read_cgroup_v2_memory(target)
 	} else {
-		read_cgroup_v1_memory()
+		read_cgroup_v1_memory(target)
 	}
 }
 
@@ -30,18 +50,126 @@ fn uses_cgroup_v2() -> bool {
 	return Path::new("/sys/fs/cgroup/cgroup.controllers").exists();
 }
 
-fn read_cgroup_v2_memory() -> Result<CGroupMemory, CGroupError> {
-	let mut controller_path = Path::new("/sys/fs/cgroup").join(read_cgroupv2_controller()?.strip_prefix("/").unwrap_or(""));
+fn is_cgroup_v2(target: &CgroupTarget) -> bool {
+	match target {
+		CgroupTarget::Own | CgroupTarget::Pid(_) => uses_cgroup_v2(),
+		// on hybrid systems a v1 memory cgroup may be given although v2 is mounted as well
+		CgroupTarget::Path(path) => path.join("cgroup.controllers").exists(),
+	}
+}
 
-	loop {
-		let mem_max = controller_path.join("memory.max");
-		let mem_current = controller_path.join("memory.current");
+pub fn cgroup_memory_pressure_path(target: &CgroupTarget) -> Result<Option<PathBuf>, CGroupError> {
+	if !is_cgroup_v2(target) {
+		return Ok(None);
+	}
+	let pressure = cgroup_v2_memory_path(target)?.join("memory.pressure");
+	Ok(if pressure.exists() { Some(pressure) } else { None })
+}
 
-		if mem_max.exists() && mem_current.exists() {
-			let (limit, unlimited) = read_file_usize(mem_max)?;
-			let (usage, _) = read_file_usize(mem_current)?;
+// the cgroup directory the target is in, as opposed to the one its memory limit comes from
+pub fn cgroup_dir(target: &CgroupTarget) -> Result<PathBuf, CGroupError> {
+	match target {
+		CgroupTarget::Path(path) => Ok(path.clone()),
+		_ if uses_cgroup_v2() => {
+			let controller = read_cgroupv2_controller(target)?;
+			below(Path::new("/sys/fs/cgroup"), Path::new(&controller)).ok_or(CGroupError::NotVisible(controller))
+		}
+		_ => cgroup_v1_dir(target),
+	}
+}
+
+fn proc_cgroup_path(target: &CgroupTarget) -> PathBuf {
+	match target {
+		CgroupTarget::Pid(pid) => PathBuf::from(format!("/proc/{}/cgroup", pid)),
+		_ => PathBuf::from("/proc/self/cgroup"),
+	}
+}
 
-			return Ok(CGroupMemory { usage, limit, unlimited });
+// moves the process into the cgroup, memory it allocates afterwards is charged to it
+pub fn join(dir: &Path, pid: u32) -> Result<(), CGroupError> {
+	let path = dir.join("cgroup.procs");
+	fs::write(&path, pid.to_string()).map_err(|e| CGroupError::File(path, e))
+}
+
+// cgroup created by memfill, removed again together with the memory controller it enabled for it
+pub struct ChildCgroup {
+	pub path: PathBuf,
+	enabled_memory: bool,
+}
+
+impl ChildCgroup {
+	// only succeeds once all processes have left the cgroup
+	pub fn remove(&self) -> Result<(), CGroupError> {
+		fs::remove_dir(&self.path).map_err(|e| CGroupError::File(self.path.clone(), e))?;
+		if let (true, Some(parent)) = (self.enabled_memory, self.path.parent()) {
+			disable_memory(parent);
+		}
+		Ok(())
+	}
+}
+
+// creates a cgroup v2 below the parent with the memory controller enabled,
+// which requires the parent to have no processes of its own unless it is the root
+pub fn create_child(parent: &Path, name: &str) -> Result<ChildCgroup, CGroupError> {
+	if !parent.join("cgroup.controllers").exists() {
+		return Err(CGroupError::NotCgroupV2(parent.to_path_buf()));
+	}
+	let subtree_control = parent.join("cgroup.subtree_control");
+	let controllers = fs::read_to_string(&subtree_control).map_err(|e| CGroupError::File(subtree_control.clone(), e))?;
+	let enabled_memory = !controllers.split_whitespace().any(|c| c == "memory");
+	if enabled_memory {
+		// no internal processes: only the root cgroup may enable controllers while it has processes itself
+		let procs = parent.join("cgroup.procs");
+		let populated = !fs::read_to_string(&procs).map_err(|e| CGroupError::File(procs, e))?.trim().is_empty();
+		if populated && parent.join("cgroup.type").exists() {
+			return Err(CGroupError::Populated(parent.to_path_buf()));
+		}
+		fs::write(&subtree_control, "+memory").map_err(|e| CGroupError::File(subtree_control, e))?;
+	}
+	let path = parent.join(name);
+	if let Err(e) = fs::create_dir(&path) {
+		if enabled_memory {
+			disable_memory(parent);
+		}
+		return Err(CGroupError::File(path, e));
+	}
+	Ok(ChildCgroup { path, enabled_memory })
+}
+
+// fails while other children still use the controller, they keep it enabled then
+fn disable_memory(parent: &Path) {
+	let _ = fs::write(parent.join("cgroup.subtree_control"), "-memory");
+}
+
+pub fn write_value(dir: &Path, file: &str, value: &str) -> Result<(), CGroupError> {
+	let path = dir.join(file);
+	fs::write(&path, value).map_err(|e| CGroupError::File(path, e))
+}
+
+// usage of exactly this cgroup v2, without walking up to the one the limit comes from
+pub fn read_memory_current(dir: &Path) -> Result<usize, CGroupError> {
+	read_file_usize(dir.join("memory.current")).map(|(usage, _)| usage)
+}
+
+fn read_cgroup_v2_memory(target: &CgroupTarget) -> Result<CGroupMemory, CGroupError> {
+	let controller_path = cgroup_v2_memory_path(target)?;
+	let (limit, unlimited) = read_file_usize(controller_path.join("memory.max"))?;
+	let (usage, _) = read_file_usize(controller_path.join("memory.current"))?;
+	let high = read_optional_file_usize(controller_path.join("memory.high"))?;
+	let low = read_optional_file_usize(controller_path.join("memory.low"))?;
+	let min = read_optional_file_usize(controller_path.join("memory.min"))?;
+	let swap_usage = read_optional_file_usize(controller_path.join("memory.swap.current"))?;
+	let swap_limit = read_optional_file_usize(controller_path.join("memory.swap.max"))?;
+
+	Ok(CGroupMemory { usage, limit, unlimited, high, low, min, swap_usage, swap_limit })
+}
+
+fn cgroup_v2_memory_path(target: &CgroupTarget) -> Result<PathBuf, CGroupError> {
+	let mut controller_path = cgroup_dir(target)?;
+
+	loop {
+		if controller_path.join("memory.max").exists() && controller_path.join("memory.current").exists() {
+			return Ok(controller_path);
 		}
 
 		match controller_path.parent() {
@@ -51,12 +179,12 @@ fn read_cgroup_v2_memory() -> Result<CGroupMemory, CGroupError> {
 	}
 }
 
-fn read_cgroupv2_controller() -> Result<String, CGroupError> {
-	let path = PathBuf::from("/proc/self/cgroup");
+fn read_cgroupv2_controller(target: &CgroupTarget) -> Result<String, CGroupError> {
+	let path = proc_cgroup_path(target);
 	let file = File::open(path.as_path()).map_err(|e| CGroupError::File(path, e))?;
 	let lines = io::BufReader::new(file).lines();
 
-	for line in lines.flatten() {
+	for line in lines.map_while(Result::ok) {
 		let parts: Vec<&str> = line.splitn(3, ":").collect();
 		if parts[0] == "0" {
 			return Ok(parts[2].to_string());
@@ -65,21 +193,134 @@ fn read_cgroupv2_controller() -> Result<String, CGroupError> {
 	Err(CGroupError::CgroupControllerNotFound())
 }
 
-fn read_cgroup_v1_memory() -> Result<CGroupMemory, CGroupError> {
-	let controller_path = Path::new("/sys/fs/cgroup/memory").join(read_cgroupv1_controller()?.strip_prefix("/").unwrap_or(""));
+// counters of memory.stat named like in v2, for v1 kernel and sock are taken from the kmem usage files
+pub fn read_cgroup_memory_stat(target: &CgroupTarget) -> Result<HashMap<String, usize>, CGroupError> {
+	let v2 = is_cgroup_v2(target);
+	let controller_path = if v2 { cgroup_v2_memory_path(target)? } else { cgroup_v1_memory_path(target)? };
+	let mut stat = read_memory_stat_file(controller_path.join("memory.stat"))?;
+
+	if !v2 {
+		for (key, renamed) in [("dirty", "file_dirty"), ("writeback", "file_writeback")] {
+			if let Some(value) = stat.remove(key) {
+				stat.insert(renamed.to_string(), value);
+			}
+		}
+		if let Some(kernel) = read_optional_file_usize(controller_path.join("memory.kmem.usage_in_bytes"))? {
+			stat.insert("kernel".to_string(), kernel);
+		}
+		if let Some(sock) = read_optional_file_usize(controller_path.join("memory.kmem.tcp.usage_in_bytes"))? {
+			stat.insert("sock".to_string(), sock);
+		}
+	}
+	Ok(stat)
+}
+
+fn read_memory_stat_file(path: PathBuf) -> Result<HashMap<String, usize>, CGroupError> {
+	let content = fs::read_to_string(&path).map_err(|e| CGroupError::File(path.clone(), e))?;
+	let mut stat = HashMap::new();
+	for line in content.lines() {
+		let Some((key, value)) = line.split_once(' ') else { continue };
+		let value = value.trim().parse().map_err(|e| CGroupError::Parse(path.clone(), e))?;
+		stat.insert(key.to_string(), value);
+	}
+	Ok(stat)
+}
+
+fn read_cgroup_v1_memory(target: &CgroupTarget) -> Result<CGroupMemory, CGroupError> {
+	let controller_path = cgroup_v1_memory_path(target)?;
+	let stat = read_memory_stat_file(controller_path.join("memory.stat"))?;
 
 	let (usage, _) = read_file_usize(controller_path.join("memory.usage_in_bytes"))?;
-	let (limit, _) = read_file_usize(controller_path.join("memory.limit_in_bytes"))?;
+	// a parent's limit applies as well, the hierarchical limit is the lowest of them
+	let (own_limit, _) = read_file_usize(controller_path.join("memory.limit_in_bytes"))?;
+	let limit = stat.get("hierarchical_memory_limit").map_or(own_limit, |l| own_limit.min(*l));
+	let unlimited = limit == cgroup_v1_mem_unlimited();
 
-	Ok(CGroupMemory { usage, limit, unlimited: limit == cgroup_v1_mem_unlimited() })
+	// memsw accounts memory and swap together, only present with swap accounting enabled
+	let memsw_usage = read_optional_file_usize(controller_path.join("memory.memsw.usage_in_bytes"))?;
+	let memsw_limit = read_optional_file_usize(controller_path.join("memory.memsw.limit_in_bytes"))?
+		.map(|l| stat.get("hierarchical_memsw_limit").map_or(l, |h| l.min(*h)));
+	let swap_usage = memsw_usage.map(|u| u.saturating_sub(usage));
+	let swap_limit = memsw_limit.filter(|l| *l != cgroup_v1_mem_unlimited() && !unlimited).map(|l| l.saturating_sub(limit));
+
+	Ok(CGroupMemory { usage, limit, unlimited, high: None, low: None, min: None, swap_usage, swap_limit })
+}
+
+// like for v2 walks up to the first directory with the memory controller files,
+// but not beyond the mount point of the memory hierarchy
+fn cgroup_v1_memory_path(target: &CgroupTarget) -> Result<PathBuf, CGroupError> {
+	let (mount_point, _) = cgroup_v1_memory_mount()?;
+	find_v1_memory_controller(cgroup_dir(target)?, &mount_point)
 }
 
-fn read_cgroupv1_controller() -> Result<String, CGroupError> {
-	let path = PathBuf::from("/proc/self/cgroup");
+// walks up from the cgroup to the mount point until it finds the memory controller files
+fn find_v1_memory_controller(mut controller_path: PathBuf, mount_point: &Path) -> Result<PathBuf, CGroupError> {
+	loop {
+		if controller_path.join("memory.limit_in_bytes").exists() && controller_path.join("memory.usage_in_bytes").exists() {
+			return Ok(controller_path);
+		}
+
+		match controller_path.parent() {
+			Some(p) if controller_path != mount_point => { controller_path = p.to_owned(); }
+			_ => return Err(CGroupError::CgroupControllerNotFound())
+		}
+	}
+}
+
+fn cgroup_v1_dir(target: &CgroupTarget) -> Result<PathBuf, CGroupError> {
+	let (mount_point, mount_root) = cgroup_v1_memory_mount()?;
+	let controller = read_cgroupv1_controller(target)?;
+	match (cgroup_v1_mounted_dir(&mount_point, &mount_root, &controller), target) {
+		(Some(dir), _) => Ok(dir),
+		// memfill's own cgroup is the root of the mount within a container
+		(None, CgroupTarget::Own) => Ok(mount_point),
+		// another process' cgroup mustn't be mixed up with memfill's own
+		(None, _) => Err(CGroupError::NotVisible(controller)),
+	}
+}
+
+// the directory of the cgroup unless it is not visible from this namespace
+fn cgroup_v1_mounted_dir(mount_point: &Path, mount_root: &str, controller: &str) -> Option<PathBuf> {
+	// the mount may only expose a part of the hierarchy, e.g. the container's own cgroup
+	let relative = Path::new(controller).strip_prefix(mount_root).unwrap_or(Path::new(controller));
+	below(mount_point, relative).filter(|dir| dir.exists())
+}
+
+// cgroup paths of other cgroup namespaces are relative to ours, e.g. /../other
+fn below(base: &Path, path: &Path) -> Option<PathBuf> {
+	if path.components().any(|c| c == Component::ParentDir) {
+		return None;
+	}
+	Some(base.join(path.strip_prefix("/").unwrap_or(path)))
+}
+
+// mount point of the v1 memory hierarchy and the cgroup it exposes
+fn cgroup_v1_memory_mount() -> Result<(PathBuf, String), CGroupError> {
+	let path = PathBuf::from("/proc/self/mountinfo");
 	let file = File::open(path.as_path()).map_err(|e| CGroupError::File(path, e))?;
 	let lines = io::BufReader::new(file).lines();
 
-	for line in lines.flatten() {
+	let mount = lines.map_while(Result::ok).find_map(|line| parse_v1_memory_mount(&line));
+	Ok(mount.unwrap_or_else(|| (PathBuf::from("/sys/fs/cgroup/memory"), "/".to_string())))
+}
+
+// 36 32 0:32 / /sys/fs/cgroup/memory rw,relatime shared:15 - cgroup cgroup rw,memory
+fn parse_v1_memory_mount(line: &str) -> Option<(PathBuf, String)> {
+	let (mount, fs) = line.split_once(" - ")?;
+	let mount: Vec<&str> = mount.split(' ').collect();
+	let fs: Vec<&str> = fs.split(' ').collect();
+	if mount.len() > 4 && fs.len() > 2 && fs[0] == "cgroup" && fs[2].split(',').any(|o| o == "memory") {
+		return Some((PathBuf::from(mount[4]), mount[3].to_string()));
+	}
+	None
+}
+
+fn read_cgroupv1_controller(target: &CgroupTarget) -> Result<String, CGroupError> {
+	let path = proc_cgroup_path(target);
+	let file = File::open(path.as_path()).map_err(|e| CGroupError::File(path, e))?;
+	let lines = io::BufReader::new(file).lines();
+
+	for line in lines.map_while(Result::ok) {
 		let parts: Vec<&str> = line.splitn(3, ":").collect();
 		if parts[1] == "memory" {
 			return Ok(parts[2].to_string());
@@ -104,3 +345,87 @@ fn read_file_usize<P: AsRef<Path>>(path: P) -> Result<(usize, bool), CGroupError
 		Ok((i, false))
 	}
 }
+
+fn read_optional_file_usize<P: AsRef<Path>>(path: P) -> Result<Option<usize>, CGroupError> {
+	if !path.as_ref().exists() {
+		return Ok(None);
+	}
+	let (value, unlimited) = read_file_usize(path)?;
+	Ok(if unlimited { None } else { Some(value) })
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// an empty directory below the temp dir, removed again by the caller
+	fn temp_dir(name: &str) -> PathBuf {
+		let dir = std::env::temp_dir().join(format!("memfill-{}-{}", std::process::id(), name));
+		let _ = fs::remove_dir_all(&dir);
+		fs::create_dir_all(&dir).unwrap();
+		dir
+	}
+
+	#[test]
+	fn parse_memory_mount_lines() {
+		let mount = parse_v1_memory_mount("36 32 0:32 / /sys/fs/cgroup/memory rw,nosuid,nodev,noexec,relatime - cgroup cgroup rw,memory");
+		assert_eq!(mount, Some((PathBuf::from("/sys/fs/cgroup/memory"), "/".to_string())));
+		// optional fields before the separator
+		let mount = parse_v1_memory_mount("36 32 0:32 / /sys/fs/cgroup/memory rw,relatime shared:15 master:2 - cgroup cgroup rw,memory");
+		assert_eq!(mount, Some((PathBuf::from("/sys/fs/cgroup/memory"), "/".to_string())));
+		// a container only seeing its own cgroup
+		let mount = parse_v1_memory_mount("1170 1167 0:32 /docker/abc /sys/fs/cgroup/memory ro,nosuid master:15 - cgroup cgroup rw,memory");
+		assert_eq!(mount, Some((PathBuf::from("/sys/fs/cgroup/memory"), "/docker/abc".to_string())));
+	}
+
+	#[test]
+	fn parse_other_mount_lines() {
+		assert_eq!(parse_v1_memory_mount("35 32 0:31 / /sys/fs/cgroup/cpu,cpuacct rw,relatime shared:14 - cgroup cgroup rw,cpu,cpuacct"), None);
+		assert_eq!(parse_v1_memory_mount("31 25 0:27 / /sys/fs/cgroup/unified rw,relatime shared:10 - cgroup2 cgroup2 rw,memory_recursiveprot"), None);
+		assert_eq!(parse_v1_memory_mount("22 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw"), None);
+		assert_eq!(parse_v1_memory_mount("garbage"), None);
+	}
+
+	#[test]
+	fn mounted_dir_strips_mount_root() {
+		let mount_point = temp_dir("mounted");
+		fs::create_dir_all(mount_point.join("nested")).unwrap();
+
+		assert_eq!(cgroup_v1_mounted_dir(&mount_point, "/", "/nested"), Some(mount_point.join("nested")));
+		assert_eq!(cgroup_v1_mounted_dir(&mount_point, "/docker/abc", "/docker/abc/nested"), Some(mount_point.join("nested")));
+		assert_eq!(cgroup_v1_mounted_dir(&mount_point, "/docker/abc", "/docker/abc"), Some(mount_point.clone()));
+		// not visible from the mount, e.g. another namespace
+		assert_eq!(cgroup_v1_mounted_dir(&mount_point, "/docker/abc", "/docker/other"), None);
+		assert_eq!(cgroup_v1_mounted_dir(&mount_point, "/", "/../other"), None);
+
+		fs::remove_dir_all(&mount_point).unwrap();
+	}
+
+	#[test]
+	fn paths_of_other_namespaces_are_not_below() {
+		let root = Path::new("/sys/fs/cgroup");
+		assert_eq!(below(root, Path::new("/")), Some(root.to_path_buf()));
+		assert_eq!(below(root, Path::new("/system.slice/docker.service")), Some(root.join("system.slice/docker.service")));
+		assert_eq!(below(root, Path::new("/../x")), None);
+		assert_eq!(below(root, Path::new("/a/../../x")), None);
+	}
+
+	#[test]
+	fn memory_controller_walks_up_to_mount_point() {
+		let mount_point = temp_dir("walk");
+		let limited = mount_point.join("limited");
+		let nested = limited.join("nested");
+		fs::create_dir_all(&nested).unwrap();
+		for file in ["memory.limit_in_bytes", "memory.usage_in_bytes"] {
+			fs::write(limited.join(file), "0").unwrap();
+		}
+
+		assert_eq!(find_v1_memory_controller(nested.clone(), &mount_point).unwrap(), limited);
+		assert_eq!(find_v1_memory_controller(limited.clone(), &mount_point).unwrap(), limited);
+		// stops at the mount point instead of walking up the file system
+		fs::remove_file(limited.join("memory.usage_in_bytes")).unwrap();
+		assert!(matches!(find_v1_memory_controller(nested, &mount_point), Err(CGroupError::CgroupControllerNotFound())));
+
+		fs::remove_dir_all(&mount_point).unwrap();
+	}
+}
@@ -0,0 +1,66 @@
+use std::fs;
+
+use nix::sys::mman::MmapAdvise;
+
+use crate::region::Region;
+
+const BUDDYINFO: &str = "/proc/buddyinfo";
+const PAGETYPEINFO: &str = "/proc/pagetypeinfo";
+
+// of every keep + free pages the first keep pages stay allocated
+#[derive(Debug, Clone, Copy)]
+pub struct FragmentPattern {
+	keep: usize,
+	free: usize,
+}
+
+// accepts keep:free in pages, e.g. 1:1 frees every other page
+pub fn parse_fragment_pattern(input: impl AsRef<str>) -> Result<FragmentPattern, String> {
+	let input = input.as_ref();
+	let (keep, free) = input.split_once(':').ok_or(format!("expected keep:free, got {}", input))?;
+	let keep: usize = keep.parse().map_err(|e| format!("{}", e))?;
+	let free: usize = free.parse().map_err(|e| format!("{}", e))?;
+	if keep == 0 || free == 0 {
+		return Err("keep and free must be at least 1 page".to_string());
+	}
+	Ok(FragmentPattern { keep, free })
+}
+
+impl FragmentPattern {
+	// length of the region to allocate so that size stays allocated after fragmenting
+	pub fn region_len(&self, size: usize, page_size: usize) -> usize {
+		let pages = size.div_ceil(page_size);
+		let runs = pages.div_ceil(self.keep);
+		runs * (self.keep + self.free) * page_size
+	}
+
+	// frees the pages not to keep, leaving holes in the physical memory backing the region
+	pub fn fragment(&self, region: &Region, len: usize, page_size: usize) -> Result<(), String> {
+		let run = (self.keep + self.free) * page_size;
+		let mut offset = self.keep * page_size;
+		while offset < len {
+			region.advise_range(offset, (self.free * page_size).min(len - offset), MmapAdvise::MADV_DONTNEED)?;
+			offset += run;
+		}
+		Ok(())
+	}
+}
+
+// free pages per order of the buddy allocator, overall and per migrate type
+pub fn print_free_pages(label: &str) {
+	match fs::read_to_string(BUDDYINFO) {
+		Ok(content) => print!("{} {}:\n{}", BUDDYINFO, label, content),
+		Err(e) => eprintln!("Failed to read {}: {}", BUDDYINFO, e),
+	}
+	match fs::read_to_string(PAGETYPEINFO) {
+		Ok(content) => {
+			println!("{} {}:", PAGETYPEINFO, label);
+			content.lines()
+				.skip_while(|l| !l.starts_with("Free pages count"))
+				.take_while(|l| !l.is_empty())
+				.for_each(|l| println!("{}", l));
+		}
+		// only readable by root
+		Err(e) => eprintln!("Failed to read {}: {}", PAGETYPEINFO, e),
+	}
+}
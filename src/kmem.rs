@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::os::fd::{AsFd, AsRawFd, OwnedFd};
+use std::path::{Path, PathBuf};
+
+use nix::errno::Errno;
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+use nix::sys::resource::{getrlimit, setrlimit, Resource};
+use nix::sys::socket::{socketpair, AddressFamily, SockFlag, SockType};
+use nix::unistd;
+use procfs::{Current, Meminfo};
+use strum_macros::EnumString;
+
+// rough size of a dentry plus its inode in the slab caches, the exact size depends on the filesystem
+const DENTRY_BYTES: usize = 1024;
+const BLOCK_SIZE: usize = 64 * 1024;
+
+#[derive(EnumString, Debug, Clone, Copy)]
+pub enum KernelObject {
+	// reclaimable slab, shrinks under pressure
+	#[strum(serialize = "dentries")]
+	Dentries,
+	// pipe buffers, unreclaimable
+	#[strum(serialize = "pipes")]
+	Pipes,
+	// socket buffers, unreclaimable
+	#[strum(serialize = "sockets")]
+	Sockets,
+}
+
+pub enum KernelObjects {
+	Dir(PathBuf),
+	Fds(Vec<OwnedFd>),
+}
+
+// each pipe or socket pair needs two fds, the soft limit is usually too low
+pub fn raise_fd_limit() {
+	if let Ok((soft, hard)) = getrlimit(Resource::RLIMIT_NOFILE) {
+		if soft < hard {
+			let _ = setrlimit(Resource::RLIMIT_NOFILE, hard, hard);
+		}
+	}
+}
+
+// creates kernel objects until they hold about the given size, returns them with the size they hold.
+// running out of fds or memory midway keeps what has been created so far.
+pub fn fill(object: KernelObject, dir: &Path, size: usize) -> Result<(KernelObjects, usize), String> {
+	match object {
+		KernelObject::Dentries => fill_dentries(dir, size).map(|(dir, size)| (KernelObjects::Dir(dir), size)),
+		KernelObject::Pipes => fill_fds(size, new_pipe).map(|(fds, size)| (KernelObjects::Fds(fds), size)),
+		KernelObject::Sockets => fill_fds(size, new_socket_pair).map(|(fds, size)| (KernelObjects::Fds(fds), size)),
+	}
+}
+
+fn fill_dentries(dir: &Path, size: usize) -> Result<(PathBuf, usize), String> {
+	let path = crate::pagecache::new_file_path(dir);
+	fs::create_dir(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+	let count = size.div_ceil(DENTRY_BYTES);
+	for i in 0..count {
+		if let Err(e) = File::create(path.join(i.to_string())) {
+			if i == 0 {
+				let _ = fs::remove_dir_all(&path);
+				return Err(format!("{}: {}", path.display(), e));
+			}
+			eprintln!("Stopped creating files after {}: {}", i, e);
+			return Ok((path, i * DENTRY_BYTES));
+		}
+	}
+	Ok((path, count * DENTRY_BYTES))
+}
+
+fn fill_fds(size: usize, new: fn() -> nix::Result<(OwnedFd, OwnedFd)>) -> Result<(Vec<OwnedFd>, usize), String> {
+	let block = vec![0u8; BLOCK_SIZE];
+	let mut fds = vec![];
+	let mut filled = 0;
+	while filled < size {
+		let (reader, writer) = match new() {
+			Ok(pair) => pair,
+			Err(e) if fds.is_empty() => return Err(e.to_string()),
+			Err(e) => {
+				eprintln!("Stopped creating fds after {}: {}", fds.len(), e);
+				break;
+			}
+		};
+		// the writer is non-blocking, the buffer is full when the write would block
+		loop {
+			match unistd::write(writer.as_fd(), &block[..BLOCK_SIZE.min(size - filled)]) {
+				Ok(n) => filled += n,
+				Err(Errno::EINTR) => continue,
+				Err(_) => break,
+			}
+			if filled >= size {
+				break;
+			}
+		}
+		fds.push(reader);
+		fds.push(writer);
+	}
+	Ok((fds, filled))
+}
+
+fn new_pipe() -> nix::Result<(OwnedFd, OwnedFd)> {
+	let (reader, writer) = unistd::pipe2(OFlag::O_NONBLOCK | OFlag::O_CLOEXEC)?;
+	// larger pipes need fewer fds, the size is capped by /proc/sys/fs/pipe-max-size
+	if let Ok(max) = fs::read_to_string("/proc/sys/fs/pipe-max-size") {
+		if let Ok(max) = max.trim().parse() {
+			let _ = fcntl(writer.as_raw_fd(), FcntlArg::F_SETPIPE_SZ(max));
+		}
+	}
+	Ok((reader, writer))
+}
+
+fn new_socket_pair() -> nix::Result<(OwnedFd, OwnedFd)> {
+	socketpair(AddressFamily::Unix, SockType::Stream, None, SockFlag::SOCK_NONBLOCK | SockFlag::SOCK_CLOEXEC)
+}
+
+pub fn release(objects: KernelObjects) -> Result<(), String> {
+	match objects {
+		KernelObjects::Dir(path) => fs::remove_dir_all(&path).map_err(|e| format!("{}: {}", path.display(), e)),
+		KernelObjects::Fds(fds) => {
+			// closing both ends frees the buffers
+			drop(fds);
+			Ok(())
+		}
+	}
+}
+
+pub struct KernelMemory {
+	pub slab: usize,
+	pub slab_reclaimable: usize,
+	pub slab_unreclaimable: usize,
+	// only when memory is computed for a cgroup
+	pub cgroup_kernel: Option<usize>,
+	pub cgroup_sock: Option<usize>,
+}
+
+impl KernelMemory {
+	pub fn read(stat: Option<&HashMap<String, usize>>) -> Result<Self, String> {
+		let meminfo = Meminfo::current().map_err(|e| e.to_string())?;
+		let stat_value = |key: &str| stat.and_then(|s| s.get(key)).copied();
+		Ok(Self {
+			slab: meminfo.slab as usize,
+			slab_reclaimable: meminfo.s_reclaimable.unwrap_or(0) as usize,
+			slab_unreclaimable: meminfo.s_unreclaim.unwrap_or(0) as usize,
+			cgroup_kernel: stat_value("kernel"),
+			cgroup_sock: stat_value("sock"),
+		})
+	}
+}
@@ -0,0 +1,13 @@
+use std::fs;
+
+const PAGES_SHARING: &str = "/sys/kernel/mm/ksm/pages_sharing";
+
+pub fn is_running() -> bool {
+	fs::read_to_string("/sys/kernel/mm/ksm/run").is_ok_and(|r| r.trim() == "1")
+}
+
+// pages deduplicated by ksm across the system, i.e. memory saved by merging
+pub fn pages_sharing() -> Result<usize, String> {
+	let content = fs::read_to_string(PAGES_SHARING).map_err(|e| format!("{}: {}", PAGES_SHARING, e))?;
+	content.trim().parse().map_err(|e| format!("{}: {}", PAGES_SHARING, e))
+}
@@ -1,16 +1,37 @@
+#![allow(clippy::needless_return)]
+
 mod cgroup;
+mod fragment;
+mod kmem;
+mod ksm;
+mod namespace;
+mod numa;
+mod pagecache;
+mod pattern;
+mod psi;
+mod region;
+mod scenario;
+mod shm;
+mod stream;
+mod touch;
+mod worker;
 
-use std::{fs, process, str};
-use std::alloc::{alloc, dealloc, Layout};
-use std::os::fd::{AsFd, AsRawFd};
-use std::ptr::{copy, write_bytes};
+use std::{fs, mem, process, str};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::os::fd::{AsFd, AsRawFd, OwnedFd};
+use std::ptr::null_mut;
+use std::slice;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread::sleep;
 use std::time::{Duration, Instant};
 
 use bytesize::ByteSize;
 use duration_str::parse as parse_duration;
 use nix::errno::Errno;
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
 use nix::sys::{prctl, signal};
+use nix::sys::mman::MmapAdvise;
 use nix::sys::signal::{SIGCONT, Signal};
 use nix::sys::signal::{SaFlags, sigaction, SigAction, SigHandler, SigSet};
 use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
@@ -18,56 +39,369 @@ use nix::{libc, unistd};
 use nix::unistd::{fork, Pid, Uid};
 use nix::unistd::ForkResult;
 use procfs::{Current, Meminfo};
+use serde::Deserialize;
 use structopt::{StructOpt};
 use strum_macros::EnumString;
 
 #[derive(StructOpt, Debug)]
 #[structopt(name = "memfill", about = "Fills memory")]
 struct Opt {
-	#[structopt(help = "Size of memory to fill up; suffixes: K, M, G or %", parse(
+	#[structopt(help = "Size of memory to fill up; suffixes: K, M, G or %; relative to a cgroup limit: max, high, low or min with optional +/- offset, e.g. high+5%; stall percentage for psi; swap usage (% of total swap) for swap", required_unless = "scenario", parse(
 		try_from_str = parse_size
 	))]
-	size: Size,
+	size: Option<Size>,
+
+	#[structopt(help = "Allocation mode; [absolute, usage, ramp, sine, sawtooth, square, leak, psi, swap]", required_unless = "scenario")]
+	alloc_mode: Option<AllocationMode>,
 
-	#[structopt(help = "Allocation mode; [absolute, usage]")]
-	alloc_mode: AllocationMode,
+	#[structopt(help = "Duration; suffixes: s, m, h, d", required_unless = "scenario", parse(try_from_str = parse_duration))]
+	duration: Option<Duration>,
 
-	#[structopt(help = "Duration; suffixes: s, m, h, d", parse(try_from_str = parse_duration))]
-	duration: Duration,
+	#[structopt(long, help = "scenario file (yaml, json or toml) with a sequence of phases; replaces size, alloc-mode and duration", conflicts_with_all = &["size", "alloc-mode", "duration"])]
+	scenario: Option<PathBuf>,
 
 	#[structopt(long, help = "ignore cgroup; computes total/usage from system information")]
 	ignore_cgroup: bool,
+
+	#[structopt(long, help = "computes total/usage from this cgroup directory instead of memfill's own cgroup, either cgroup v2 or the v1 memory controller", conflicts_with = "ignore-cgroup", parse(from_os_str))]
+	cgroup: Option<PathBuf>,
+
+	#[structopt(long, help = "move the processes holding the allocated memory into the cgroup given by --cgroup; memfill itself for backends without processes", requires = "cgroup")]
+	join: bool,
+
+	#[structopt(long, help = "fill the cgroup of this process, e.g. a container; the processes holding the allocated memory are moved into it", conflicts_with_all = &["ignore-cgroup", "cgroup"])]
+	target_pid: Option<u32>,
+
+	#[structopt(long, help = "process: create a cgroup v2 of this name below the target cgroup for the processes holding the allocated memory, removed on exit; the target cgroup must not contain processes itself unless it is the root cgroup, so memfill's own cgroup or that of --target-pid usually don't work, pass an empty --cgroup instead", conflicts_with = "ignore-cgroup")]
+	sub_cgroup: Option<String>,
+
+	#[structopt(long, help = "memory.max of the cgroup created by --sub-cgroup, e.g. 1G", requires = "sub-cgroup")]
+	sub_cgroup_max: Option<ByteSize>,
+
+	#[structopt(long, help = "memory.high of the cgroup created by --sub-cgroup, e.g. 1G", requires = "sub-cgroup")]
+	sub_cgroup_high: Option<ByteSize>,
+
+	#[structopt(long, help = "set memory.oom.group of the cgroup created by --sub-cgroup, so the oom killer kills all processes holding the allocated memory together", requires = "sub-cgroup")]
+	sub_cgroup_oom_group: bool,
+
+	#[structopt(long, help = "enter the mount and pid namespaces of --target-pid with the processes holding the allocated memory; files are created within its root for other backends", requires = "target-pid")]
+	enter_namespaces: bool,
+
+	#[structopt(flatten)]
+	mode_opt: ModeOpt,
+
+	#[structopt(flatten)]
+	chunk_opt: ChunkOpt,
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct ChunkOpt {
+	#[structopt(long, help = "where the allocated memory lives; [process, thread, mmap, pagecache, shm, memfd, kmem, dirty] where thread and mmap keep the chunks within memfill without forking, kmem consumes kernel memory instead of user pages and dirty keeps the page cache dirty", default_value = "process")]
+	backend: Backend,
+
+	#[structopt(long, help = "process, thread, mmap: page size backing the allocated memory; [normal, thp, hugetlb-2m, hugetlb-1g]", default_value = "normal")]
+	pages: region::PageBacking,
+
+	#[structopt(long, help = "content of the allocated memory; [constant, random, identical, unique, <ratio>:1] where ratio is the targeted compression ratio, e.g. 2:1; identical and unique pages are mergeable by ksm resp. not", default_value = "constant", parse(
+		try_from_str = pattern::parse_fill_pattern
+	))]
+	pattern: pattern::FillPattern,
+
+	#[structopt(long, help = "process, thread, mmap: mark the allocated memory as mergeable by ksm (kernel samepage merging)")]
+	ksm: bool,
+
+	#[structopt(long, help = "process: fragment physical memory by freeing pages of a larger region after filling it; keep:free in pages, e.g. 1:1 frees every other page", conflicts_with_all = &["mlock", "touch", "bandwidth", "bandwidth-threads"], parse(
+		try_from_str = fragment::parse_fragment_pattern
+	))]
+	fragment: Option<fragment::FragmentPattern>,
+
+	#[structopt(long, help = "bind the allocated memory to numa nodes, e.g. 0 or 0-1; total/usage are computed from these nodes", parse(
+		try_from_str = numa::parse_node_set
+	))]
+	numa_node: Option<numa::NodeSet>,
+
+	#[structopt(long, help = "interleave the allocated memory across numa nodes, e.g. 0,1; total/usage are computed from these nodes", conflicts_with = "numa-node", parse(
+		try_from_str = numa::parse_node_set
+	))]
+	numa_interleave: Option<numa::NodeSet>,
+
+	#[structopt(long, help = "process, thread, mmap: lock the allocated memory with mlock, so it can't be swapped out or reclaimed")]
+	mlock: bool,
+
+	#[structopt(long, help = "kmem: kernel objects holding the memory; [dentries, pipes, sockets] where dentries are reclaimable slab", default_value = "dentries")]
+	kmem_objects: kmem::KernelObject,
+
+	#[structopt(long, help = "pagecache, shm, kmem, dirty: directory to create the files in; defaults to /var/tmp or /dev/shm", parse(from_os_str))]
+	file_dir: Option<PathBuf>,
+
+	#[structopt(long, help = "pagecache: only drop the cached pages instead of removing the files when memory is released")]
+	keep_files: bool,
+
+	#[structopt(long, help = "dirty: interval between rewriting the files, dirtying the pages again that have been written back", default_value = "5s", parse(try_from_str = parse_duration))]
+	dirty_interval: Duration,
+
+	#[structopt(long, help = "pagecache: posix_fadvise advice for the files; [normal, sequential, random, noreuse, willneed, dontneed]")]
+	fadvise: Option<pagecache::Fadvise>,
+
+	#[structopt(long, help = "process, thread: keep allocated memory active by touching it periodically; [sequential, random, hot:<percent>]", parse(
+		try_from_str = touch::parse_touch_pattern
+	))]
+	touch: Option<touch::TouchPattern>,
+
+	#[structopt(long, help = "interval between touching the allocated memory", default_value = "1s", parse(try_from_str = parse_duration))]
+	touch_interval: Duration,
+
+	// cgroup the chunk processes move into before allocating
+	#[structopt(skip)]
+	join_cgroup: Option<PathBuf>,
+
+	// process whose mount namespace the chunk processes enter before allocating
+	#[structopt(skip)]
+	mount_namespace: Option<u32>,
+
+	// root the file directory is resolved in
+	#[structopt(skip)]
+	root: Option<PathBuf>,
+
+	#[structopt(long, help = "process, thread: stream reads and writes over the allocated memory to stress memory bandwidth; target throughput of each chunk, the total grows with the number of chunks, e.g. 2G/s", parse(
+		try_from_str = parse_rate
+	))]
+	bandwidth: Option<f64>,
+
+	#[structopt(long, help = "number of threads per chunk streaming over the allocated memory; unlimited throughput unless --bandwidth is set", parse(
+		try_from_str = parse_thread_count
+	))]
+	bandwidth_threads: Option<usize>,
 }
 
-#[derive(EnumString, Debug)]
+impl ChunkOpt {
+	// only the backends mapping anonymous memory use --pages
+	fn huge_page_size(&self) -> Option<usize> {
+		match self.backend {
+			Backend::Process | Backend::Thread | Backend::Mmap => self.pages.huge_page_size(),
+			_ => None,
+		}
+	}
+
+	fn file_dir(&self) -> PathBuf {
+		let default = match self.backend {
+			Backend::Shm => Path::new("/dev/shm"),
+			_ => Path::new("/var/tmp"),
+		};
+		let dir = self.file_dir.as_deref().unwrap_or(default);
+		match &self.root {
+			Some(root) => root.join(dir.strip_prefix("/").unwrap_or(dir)),
+			None => dir.to_path_buf(),
+		}
+	}
+
+	fn numa_policy(&self) -> Option<numa::NumaPolicy> {
+		match (&self.numa_node, &self.numa_interleave) {
+			(Some(nodes), _) => Some(numa::NumaPolicy::Bind(nodes.clone())),
+			(_, Some(nodes)) => Some(numa::NumaPolicy::Interleave(nodes.clone())),
+			_ => None,
+		}
+	}
+}
+
+#[derive(EnumString, Debug, Clone, Copy)]
+enum Backend {
+	#[strum(serialize = "process")]
+	Process,
+	#[strum(serialize = "thread")]
+	Thread,
+	#[strum(serialize = "mmap")]
+	Mmap,
+	#[strum(serialize = "pagecache")]
+	PageCache,
+	#[strum(serialize = "shm")]
+	Shm,
+	#[strum(serialize = "memfd")]
+	Memfd,
+	#[strum(serialize = "kmem")]
+	Kmem,
+	#[strum(serialize = "dirty")]
+	Dirty,
+}
+
+#[derive(StructOpt, Deserialize, Debug, Default)]
+#[serde(default)]
+struct ModeOpt {
+	#[structopt(flatten)]
+	#[serde(flatten)]
+	ramp: RampOpt,
+
+	#[structopt(flatten)]
+	#[serde(flatten)]
+	wave: WaveOpt,
+
+	#[structopt(flatten)]
+	#[serde(flatten)]
+	leak: LeakOpt,
+
+	#[structopt(flatten)]
+	#[serde(flatten)]
+	psi: PsiOpt,
+
+	// keys of a scenario phase none of the options above take, flattened structs take the keys they use
+	#[structopt(skip)]
+	#[serde(flatten)]
+	unknown: HashMap<String, serde::de::IgnoredAny>,
+}
+
+#[derive(StructOpt, Deserialize, Debug, Default)]
+#[serde(default, rename_all = "kebab-case")]
+struct RampOpt {
+	#[structopt(long, help = "ramp: time to grow from zero to size; defaults to the duration", parse(try_from_str = parse_duration))]
+	#[serde(deserialize_with = "scenario::opt_duration")]
+	ramp_up: Option<Duration>,
+
+	#[structopt(long, help = "ramp: time to shrink back to zero before the duration ends", parse(try_from_str = parse_duration))]
+	#[serde(deserialize_with = "scenario::opt_duration")]
+	ramp_down: Option<Duration>,
+
+	#[structopt(long, help = "ramp: number of equal steps instead of growing linearly")]
+	ramp_steps: Option<u32>,
+}
+
+#[derive(StructOpt, Deserialize, Debug)]
+#[serde(default, rename_all = "kebab-case")]
+struct WaveOpt {
+	#[structopt(long, help = "sine, sawtooth, square: lower bound of the wave; suffixes: K, M, G or %", parse(
+		try_from_str = parse_size
+	))]
+	#[serde(deserialize_with = "scenario::opt_size")]
+	low: Option<Size>,
+
+	#[structopt(long, help = "sine, sawtooth, square: duration of one wave period", default_value = "1m", parse(try_from_str = parse_duration))]
+	#[serde(deserialize_with = "scenario::duration")]
+	period: Duration,
+}
+
+impl Default for WaveOpt {
+	fn default() -> Self {
+		return Self { low: None, period: Duration::from_secs(60) };
+	}
+}
+
+#[derive(StructOpt, Deserialize, Debug, Default)]
+#[serde(default)]
+struct LeakOpt {
+	#[structopt(long, help = "leak: growth rate in bytes per time unit, e.g. 10M/s or 1G/h", parse(try_from_str = parse_rate))]
+	#[serde(deserialize_with = "scenario::opt_rate")]
+	rate: Option<f64>,
+}
+
+#[derive(StructOpt, Deserialize, Debug, Default)]
+#[serde(default, rename_all = "kebab-case")]
+struct PsiOpt {
+	#[structopt(long, help = "psi: pressure stall metric to hold at the target; [some, full]", default_value = "some")]
+	psi_metric: psi::PsiMetric,
+}
+
+#[derive(EnumString, Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
 enum AllocationMode {
 	#[strum(serialize = "absolute")]
 	Absolute,
 	#[strum(serialize = "usage")]
 	Usage,
+	#[strum(serialize = "ramp")]
+	Ramp,
+	#[strum(serialize = "sine")]
+	Sine,
+	#[strum(serialize = "sawtooth")]
+	Sawtooth,
+	#[strum(serialize = "square")]
+	Square,
+	#[strum(serialize = "leak")]
+	Leak,
+	#[strum(serialize = "psi")]
+	Psi,
+	#[strum(serialize = "swap")]
+	Swap,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 enum Size {
 	Bytes(usize),
 	Percent(u16),
+	Limit(MemoryLimit, LimitOffset),
+}
+
+#[derive(EnumString, strum_macros::Display, Debug, Clone, Copy)]
+enum MemoryLimit {
+	#[strum(serialize = "max")]
+	Max,
+	#[strum(serialize = "high")]
+	High,
+	#[strum(serialize = "low")]
+	Low,
+	#[strum(serialize = "min")]
+	Min,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum LimitOffset {
+	Bytes(i64),
+	Percent(i16),
 }
 
 fn parse_size(input: impl AsRef<str>) -> Result<Size, String> {
 	let input = input.as_ref();
-	if input.ends_with('%') {
+	if let Some(limit) = ["max", "high", "low", "min"].iter().find(|l| input.starts_with(*l)) {
+		let offset = &input[limit.len()..];
+		let sign = match offset.chars().next() {
+			None => return Ok(Size::Limit(limit.parse().unwrap(), LimitOffset::Bytes(0))),
+			Some('+') => 1,
+			Some('-') => -1,
+			Some(_) => return Err(format!("expected + or - after {}", limit)),
+		};
+		let offset = match parse_size(&offset[1..])? {
+			Size::Bytes(bytes) => LimitOffset::Bytes(sign * bytes as i64),
+			Size::Percent(percent) => LimitOffset::Percent(sign as i16 * percent as i16),
+			Size::Limit(..) => return Err(format!("invalid offset for {}", limit)),
+		};
+		Ok(Size::Limit(limit.parse().unwrap(), offset))
+	} else if input.ends_with('%') {
 		let percent = input.trim_end_matches('%').parse().map_err(|e| format!("{}", e))?;
 		Ok(Size::Percent(percent))
 	} else {
-		let byte: ByteSize = input.parse().map_err(|e| format!("{}", e))?;
+		let byte: ByteSize = input.parse()?;
 		Ok(Size::Bytes(byte.as_u64() as usize))
 	}
 }
 
+fn parse_thread_count(input: &str) -> Result<usize, String> {
+	match input.parse().map_err(|e| format!("{}", e))? {
+		0 => Err("at least one thread is required".to_string()),
+		threads => Ok(threads),
+	}
+}
+
+fn parse_rate(input: impl AsRef<str>) -> Result<f64, String> {
+	let (bytes, per) = input.as_ref().split_once('/').ok_or("expected <size>/<time unit>, e.g. 10M/s")?;
+	let bytes: ByteSize = bytes.parse()?;
+	let per = if per.starts_with(|c: char| c.is_ascii_digit()) { parse_duration(per) } else { parse_duration(format!("1{}", per)) }
+		.map_err(|e| e.to_string())?;
+	if per.is_zero() {
+		return Err("time unit must not be zero".to_string());
+	}
+	if bytes.as_u64() == 0 {
+		return Err("rate must be greater than zero".to_string());
+	}
+	Ok(bytes.as_u64() as f64 / per.as_secs_f64())
+}
+
 #[derive(Debug)]
 struct MemInfo {
 	available: usize,
 	total: usize,
+	high: Option<usize>,
+	low: Option<usize>,
+	min: Option<usize>,
+	swap_total: usize,
+	swap_used: usize,
 }
 
 fn bytes_to_string_i64(bytes: i64) -> String {
@@ -78,43 +412,191 @@ fn bytes_to_string_usize(bytes: usize) -> String {
 	ByteSize::b(bytes as u64).to_string_as(true)
 }
 
+fn bytes_change(now: usize, before: usize) -> String {
+	format!("{}{}", if now >= before { "+" } else { "" }, bytes_to_string_i64(now as i64 - before as i64))
+}
+
 trait MemInfoProvider {
 	fn mem_info(&self) -> MemInfo;
+	fn pressure(&self) -> Result<psi::MemoryPressure, String>;
+
+	// counters of the cgroup memfill computes the memory for
+	fn memory_stat(&self) -> Option<HashMap<String, usize>> {
+		None
+	}
 }
 
+const SYSTEM_MEMORY_PRESSURE: &str = "/proc/pressure/memory";
+
 struct SystemMemInfo {}
 
 impl MemInfoProvider for SystemMemInfo {
 	fn mem_info(&self) -> MemInfo {
 		let mem = Meminfo::current().unwrap();
-		return MemInfo { available: mem.mem_available.unwrap() as usize, total: mem.mem_total as usize };
+		return MemInfo {
+			available: mem.mem_available.unwrap() as usize,
+			total: mem.mem_total as usize,
+			high: None,
+			low: None,
+			min: None,
+			swap_total: mem.swap_total as usize,
+			swap_used: (mem.swap_total - mem.swap_free) as usize,
+		};
+	}
+
+	fn pressure(&self) -> Result<psi::MemoryPressure, String> {
+		psi::read_memory_pressure(SYSTEM_MEMORY_PRESSURE)
+	}
+}
+
+struct NumaMemInfo {
+	nodes: numa::NodeSet,
+}
+
+impl MemInfoProvider for NumaMemInfo {
+	fn mem_info(&self) -> MemInfo {
+		let system = SystemMemInfo {}.mem_info();
+		let (mut total, mut available) = (0, 0);
+		for node in &self.nodes.0 {
+			match numa::read_node_meminfo(*node) {
+				Ok(mem) => {
+					total += mem.total;
+					available += mem.available;
+				}
+				// e.g. the node went offline, memfill continues with the remaining nodes
+				Err(e) => eprintln!("Failed to read numa node memory: {}", e),
+			}
+		}
+		return MemInfo { available, total, high: None, low: None, min: None, swap_total: system.swap_total, swap_used: system.swap_used };
+	}
+
+	fn pressure(&self) -> Result<psi::MemoryPressure, String> {
+		psi::read_memory_pressure(SYSTEM_MEMORY_PRESSURE)
 	}
 }
 
-struct CgroupMemInfo {}
+// an unlimited cgroup can use all memory of the system
+fn cgroup_mem_info(mem_cgroup: &cgroup::CGroupMemory, mem_total: usize, swap_total: usize, swap_free: usize) -> MemInfo {
+	let mut total = mem_cgroup.limit;
+	if mem_cgroup.unlimited {
+		total = mem_total;
+	}
+	let available = total.saturating_sub(mem_cgroup.usage);
+	let swap_used = mem_cgroup.swap_usage.unwrap_or(swap_total - swap_free);
+	let swap_total = mem_cgroup.swap_limit.map_or(swap_total, |l| l.min(swap_total));
+
+	return MemInfo { available, total, high: mem_cgroup.high, low: mem_cgroup.low, min: mem_cgroup.min, swap_total, swap_used };
+}
+
+struct CgroupMemInfo {
+	target: cgroup::CgroupTarget,
+}
 
 impl MemInfoProvider for CgroupMemInfo {
 	fn mem_info(&self) -> MemInfo {
-		let mem_cgroup = cgroup::read_cgroup_memory().unwrap();
-		let mut total = mem_cgroup.limit;
-		if mem_cgroup.unlimited {
-			total = Meminfo::current().unwrap().mem_total as usize;
+		let mem_cgroup = cgroup::read_cgroup_memory(&self.target).unwrap();
+		let mem = Meminfo::current().unwrap();
+		return cgroup_mem_info(&mem_cgroup, mem.mem_total as usize, mem.swap_total as usize, mem.swap_free as usize);
+	}
+
+	fn pressure(&self) -> Result<psi::MemoryPressure, String> {
+		match cgroup::cgroup_memory_pressure_path(&self.target).map_err(|e| format!("{:?}", e))? {
+			Some(path) => psi::read_memory_pressure(path),
+			None => psi::read_memory_pressure(SYSTEM_MEMORY_PRESSURE),
 		}
-		let available = total - mem_cgroup.usage;
+	}
 
-		return MemInfo { available, total };
+	fn memory_stat(&self) -> Option<HashMap<String, usize>> {
+		cgroup::read_cgroup_memory_stat(&self.target).ok()
 	}
 }
 
 trait Allocator {
 	fn update(&mut self);
-	fn size(&self) -> usize;
+	fn into_chunks(self: Box<Self>) -> Chunks;
+	fn chunks(&self) -> &Chunks;
+
+	fn size(&self) -> usize {
+		self.chunks().size()
+	}
+
+	// allocation the allocator works towards, if it is known upfront
+	fn target(&self) -> Option<usize> {
+		None
+	}
+}
+
+fn new_allocator<'a>(phase: &scenario::Phase, mem_info_provider: &'a dyn MemInfoProvider, chunks: Chunks) -> Box<dyn Allocator + 'a> {
+	let opt = &phase.mode_opt;
+	match phase.mode {
+		AllocationMode::Absolute => { Box::new(AbsoluteAllocator::new(mem_info_provider, phase.size, chunks)) }
+		AllocationMode::Usage => { Box::new(UsageAllocator::new(mem_info_provider, phase.size, chunks)) }
+		AllocationMode::Ramp => { Box::new(RampAllocator::new(mem_info_provider, phase.size, phase.duration, &opt.ramp, chunks)) }
+		AllocationMode::Sine | AllocationMode::Sawtooth | AllocationMode::Square => { Box::new(WaveAllocator::new(phase.mode, mem_info_provider, phase.size, &opt.wave, chunks)) }
+		AllocationMode::Leak => { Box::new(LeakAllocator::new(mem_info_provider, phase.size, phase.duration, &opt.leak, chunks)) }
+		AllocationMode::Psi => { Box::new(PsiAllocator::new(mem_info_provider, phase.size, &opt.psi, chunks)) }
+		AllocationMode::Swap => { Box::new(SwapAllocator::new(mem_info_provider, phase.size, chunks)) }
+	}
+}
+
+fn limit_bytes(mem: &MemInfo, limit: MemoryLimit) -> Option<usize> {
+	match limit {
+		MemoryLimit::Max => Some(mem.total),
+		MemoryLimit::High => mem.high,
+		MemoryLimit::Low => mem.low,
+		MemoryLimit::Min => mem.min,
+	}
 }
 
-fn new_allocator<'a>(mode: AllocationMode, mem_info_provider: &'a dyn MemInfoProvider, size: Size) -> Box<dyn Allocator + 'a> {
-	match mode {
-		AllocationMode::Absolute => { Box::new(AbsoluteAllocator::new(mem_info_provider, size)) }
-		AllocationMode::Usage => { Box::new(UsageAllocator::new(mem_info_provider, size)) }
+// all phases are checked before the first one runs, instead of a scenario failing halfway through
+fn check_phase(phase: &scenario::Phase, provider: &dyn MemInfoProvider) -> Result<(), String> {
+	let mem = provider.mem_info();
+	let low = match phase.mode {
+		AllocationMode::Sine | AllocationMode::Sawtooth | AllocationMode::Square => phase.mode_opt.wave.low,
+		_ => None,
+	};
+	for size in [Some(phase.size), low].into_iter().flatten() {
+		if let Size::Limit(limit, _) = size {
+			if limit_bytes(&mem, limit).is_none() {
+				return Err(format!("Memory limit {} is not set", limit));
+			}
+		}
+	}
+	match phase.mode {
+		AllocationMode::Leak if phase.mode_opt.leak.rate.is_none() => Err("Allocation mode leak requires --rate".to_string()),
+		AllocationMode::Psi => {
+			if !matches!(phase.size, Size::Percent(_)) {
+				return Err("Allocation mode psi requires the size as stall percentage, e.g. 20%".to_string());
+			}
+			provider.pressure().map(|_| ()).map_err(|e| format!("Failed to read memory pressure: {}", e))
+		}
+		AllocationMode::Swap if mem.swap_total == 0 => Err("Allocation mode swap requires swap to be enabled".to_string()),
+		_ => Ok(()),
+	}
+}
+
+fn absolute_bytes(mem: &MemInfo, size: Size) -> (usize, u16) {
+	match size {
+		Size::Bytes(bytes) => {
+			let percent = (bytes as f64 / mem.total as f64 * 100.0).round() as u16;
+			(bytes, percent)
+		}
+		Size::Percent(percent) => {
+			let bytes = (mem.total as f64 * percent as f64 / 100.0) as usize;
+			(bytes, percent)
+		}
+		Size::Limit(limit, offset) => {
+			let value = limit_bytes(mem, limit).unwrap_or_else(|| {
+				eprintln!("Memory limit {} is not set", limit);
+				process::exit(1);
+			});
+			let bytes = match offset {
+				LimitOffset::Bytes(bytes) => value as i64 + bytes,
+				LimitOffset::Percent(percent) => (value as f64 * (100.0 + percent as f64) / 100.0) as i64,
+			}.max(0) as usize;
+			let percent = (bytes as f64 / mem.total as f64 * 100.0).round() as u16;
+			(bytes, percent)
+		}
 	}
 }
 
@@ -124,20 +606,10 @@ struct AbsoluteAllocator {
 }
 
 impl AbsoluteAllocator {
-	fn new(provider: &dyn MemInfoProvider, size: Size) -> Self {
-		let mem = provider.mem_info();
-		let (bytes, percent) = match size {
-			Size::Bytes(bytes) => {
-				let percent = (bytes as f64 / mem.total as f64 * 100.0).round() as u16;
-				(bytes, percent)
-			}
-			Size::Percent(percent) => {
-				let bytes = (mem.total as f64 * percent as f64 / 100.0) as usize;
-				(bytes, percent)
-			}
-		};
+	fn new(provider: &dyn MemInfoProvider, size: Size, chunks: Chunks) -> Self {
+		let (bytes, percent) = absolute_bytes(&provider.mem_info(), size);
 		println!("Allocating {} ({}% of total memory)", bytes_to_string_usize(bytes), percent);
-		return Self { bytes, chunks: Chunks::new() };
+		return Self { bytes, chunks };
 	}
 }
 
@@ -147,34 +619,33 @@ impl Allocator for AbsoluteAllocator {
 		self.chunks.resize(self.bytes)
 	}
 
-	fn size(&self) -> usize {
-		self.chunks.size()
+	fn target(&self) -> Option<usize> {
+		Some(self.bytes)
+	}
+
+	fn into_chunks(self: Box<Self>) -> Chunks {
+		self.chunks
+	}
+
+	fn chunks(&self) -> &Chunks {
+		&self.chunks
 	}
 }
 
 struct UsageAllocator<'a> {
 	available_bytes: i64,
 	chunks: Chunks,
-	provider: Box<&'a dyn MemInfoProvider>,
+	provider: &'a dyn MemInfoProvider,
 }
 
 impl<'a> UsageAllocator<'a> {
-	fn new(provider: &'a dyn MemInfoProvider, size: Size) -> Self {
+	fn new(provider: &'a dyn MemInfoProvider, size: Size, chunks: Chunks) -> Self {
 		let mem = provider.mem_info();
-		let (available_bytes, available_percent) = match size {
-			Size::Bytes(bytes) => {
-				let available_bytes = mem.total as i64 - bytes as i64;
-				let available_percent = (available_bytes as f64 / mem.total as f64 * 100.0).round() as i16;
-				(available_bytes, available_percent)
-			}
-			Size::Percent(percent) => {
-				let available_bytes = mem.total as i64 - (mem.total as f64 * percent as f64 / 100.0) as i64;
-				let available_percent = (available_bytes as f64 / mem.total as f64 * 100.0).round() as i16;
-				(available_bytes, available_percent)
-			}
-		};
+		let (bytes, _) = absolute_bytes(&mem, size);
+		let available_bytes = mem.total as i64 - bytes as i64;
+		let available_percent = (available_bytes as f64 / mem.total as f64 * 100.0).round() as i16;
 		println!("Allocate until {} ({}% of total memory) available left", bytes_to_string_i64(available_bytes), available_percent);
-		return Self { available_bytes, chunks: Chunks::new(), provider: Box::new(provider) };
+		return Self { available_bytes, chunks, provider };
 	}
 }
 
@@ -185,22 +656,365 @@ impl Allocator for UsageAllocator<'_> {
 		self.chunks.check();
 		self.chunks.adjust_by(diff)
 	}
-	fn size(&self) -> usize {
-		self.chunks.size()
+	fn into_chunks(self: Box<Self>) -> Chunks {
+		self.chunks
+	}
+
+	fn chunks(&self) -> &Chunks {
+		&self.chunks
+	}
+}
+
+struct RampAllocator {
+	bytes: usize,
+	initial: usize,
+	start: Instant,
+	ramp_up: Duration,
+	ramp_down: Duration,
+	end: Instant,
+	steps: Option<u32>,
+	step: usize,
+	chunks: Chunks,
+}
+
+// allocations smaller than this are not worth a chunk of their own
+const RAMP_MIN_STEP: usize = 16 * MB as usize;
+
+impl RampAllocator {
+	fn new(provider: &dyn MemInfoProvider, size: Size, duration: Duration, opt: &RampOpt, chunks: Chunks) -> Self {
+		let (bytes, percent) = absolute_bytes(&provider.mem_info(), size);
+		let ramp_down = opt.ramp_down.unwrap_or(Duration::ZERO).min(duration);
+		let ramp_up = opt.ramp_up.unwrap_or(duration - ramp_down);
+		let initial = chunks.size();
+		print!("Ramping from {} to {} ({}% of total memory) within {}s", bytes_to_string_usize(initial), bytes_to_string_usize(bytes), percent, ramp_up.as_secs());
+		if !ramp_down.is_zero() {
+			print!(", ramping down within the last {}s", ramp_down.as_secs());
+		}
+		println!();
+		// like for leaks limit the number of chunk processes instead of forking one per update
+		let step = ((bytes.abs_diff(initial).max(bytes) as f64 / MAX_STEP_CHUNKS) as usize).max(RAMP_MIN_STEP);
+		let start = Instant::now();
+		return Self { bytes, initial, start, ramp_up, ramp_down, end: start + duration, steps: opt.ramp_steps, step, chunks };
+	}
+
+	fn target_at(&self, now: Instant) -> usize {
+		let up = self.initial as f64 + (self.bytes as f64 - self.initial as f64) * self.step(Self::fraction(now - self.start, self.ramp_up));
+		let down = self.bytes as f64 * self.step(Self::fraction(self.end.saturating_duration_since(now), self.ramp_down));
+		up.min(down) as usize
+	}
+
+	fn step(&self, fraction: f64) -> f64 {
+		match self.steps {
+			Some(steps) if steps > 0 => (fraction * steps as f64).floor() / steps as f64,
+			_ => fraction,
+		}
+	}
+
+	fn fraction(elapsed: Duration, period: Duration) -> f64 {
+		if period.is_zero() {
+			1.0
+		} else {
+			(elapsed.as_secs_f64() / period.as_secs_f64()).min(1.0)
+		}
+	}
+}
+
+impl Allocator for RampAllocator {
+	fn update(&mut self) {
+		let target = self.target_at(Instant::now());
+		self.chunks.check();
+		let mut size = self.chunks.size();
+		// a linear ramp moves in whole steps, except for reaching either end; --ramp-steps are held exactly
+		let exact = target == self.bytes || target == 0 || self.steps.is_some_and(|steps| steps > 0);
+		if target > size {
+			while size < target && (target - size >= self.step || exact) {
+				let chunk = self.step.min(target - size);
+				self.chunks.push(chunk);
+				size += chunk;
+			}
+		} else if size > target && (size - target >= self.step || exact) {
+			// releasing whole steps frees whole chunks without allocating a remainder
+			let release = if exact { size - target } else { (size - target) / self.step * self.step };
+			self.chunks.adjust_by(-(release as i64))
+		}
+	}
+
+	fn target(&self) -> Option<usize> {
+		Some(self.bytes)
+	}
+
+	fn into_chunks(self: Box<Self>) -> Chunks {
+		self.chunks
+	}
+
+	fn chunks(&self) -> &Chunks {
+		&self.chunks
+	}
+}
+
+struct WaveAllocator {
+	mode: AllocationMode,
+	low: usize,
+	high: usize,
+	period: Duration,
+	start: Instant,
+	chunks: Chunks,
+}
+
+impl WaveAllocator {
+	fn new(mode: AllocationMode, provider: &dyn MemInfoProvider, size: Size, opt: &WaveOpt, chunks: Chunks) -> Self {
+		let mem = provider.mem_info();
+		let (high, high_percent) = absolute_bytes(&mem, size);
+		let (low, low_percent) = absolute_bytes(&mem, opt.low.unwrap_or(Size::Bytes(0)));
+		println!("Oscillating ({:?}) between {} ({}% of total memory) and {} ({}% of total memory) every {}s",
+				 mode, bytes_to_string_usize(low), low_percent, bytes_to_string_usize(high), high_percent, opt.period.as_secs());
+		return Self { mode, low, high, period: opt.period, start: Instant::now(), chunks };
+	}
+
+	fn target_at(&self, now: Instant) -> usize {
+		let phase = if self.period.is_zero() {
+			0.0
+		} else {
+			((now - self.start).as_secs_f64() / self.period.as_secs_f64()).fract()
+		};
+		let fraction = match self.mode {
+			AllocationMode::Sine => 0.5 - 0.5 * (phase * 2.0 * std::f64::consts::PI).cos(),
+			AllocationMode::Sawtooth => phase,
+			_ => if phase < 0.5 { 0.0 } else { 1.0 },
+		};
+		self.low + (self.high.saturating_sub(self.low) as f64 * fraction) as usize
+	}
+}
+
+impl Allocator for WaveAllocator {
+	fn update(&mut self) {
+		let target = self.target_at(Instant::now());
+		self.chunks.check();
+		self.chunks.resize(target)
+	}
+
+	fn target(&self) -> Option<usize> {
+		Some(self.high)
+	}
+
+	fn into_chunks(self: Box<Self>) -> Chunks {
+		self.chunks
+	}
+
+	fn chunks(&self) -> &Chunks {
+		&self.chunks
+	}
+}
+
+// upper bound for the number of chunks a gradually growing allocation is split into
+const MAX_STEP_CHUNKS: f64 = 256.0;
+
+// leaking less than this per chunk would fork a process for a few bytes
+const LEAK_MIN_STEP: usize = MB as usize;
+
+struct LeakAllocator {
+	cap: usize,
+	rate: f64,
+	step: usize,
+	initial: usize,
+	start: Instant,
+	chunks: Chunks,
+}
+
+impl LeakAllocator {
+	fn new(provider: &dyn MemInfoProvider, size: Size, duration: Duration, opt: &LeakOpt, chunks: Chunks) -> Self {
+		let rate = opt.rate.unwrap_or_else(|| {
+			eprintln!("Allocation mode leak requires --rate");
+			process::exit(1);
+		});
+		let (cap, percent) = absolute_bytes(&provider.mem_info(), size);
+		let initial = chunks.size();
+		// one chunk per second of growth, but limit the number of chunk processes for slow and long leaks.
+		// slower leaks grow by a chunk of the minimum size once enough has accumulated
+		let expected = (cap.saturating_sub(initial) as f64).min(rate * duration.as_secs_f64());
+		let step = (rate.max(expected / MAX_STEP_CHUNKS) as usize).max(LEAK_MIN_STEP);
+		println!("Leaking {}/s in chunks of {} up to {} ({}% of total memory)", bytes_to_string_usize(rate as usize), bytes_to_string_usize(step), bytes_to_string_usize(cap), percent);
+		return Self { cap, rate, step, initial, start: Instant::now(), chunks };
+	}
+}
+
+impl Allocator for LeakAllocator {
+	fn update(&mut self) {
+		self.chunks.check();
+		let target = (self.initial + (self.rate * self.start.elapsed().as_secs_f64()) as usize).min(self.cap);
+		let mut size = self.chunks.size();
+		while size < target && (target - size >= self.step || target == self.cap) {
+			let chunk = self.step.min(target - size);
+			self.chunks.push(chunk);
+			size += chunk;
+		}
+	}
+
+	fn target(&self) -> Option<usize> {
+		Some(self.cap)
+	}
+
+	fn into_chunks(self: Box<Self>) -> Chunks {
+		self.chunks
+	}
+
+	fn chunks(&self) -> &Chunks {
+		&self.chunks
+	}
+}
+
+const PSI_ADJUST_INTERVAL: Duration = Duration::from_secs(2);
+
+struct PsiAllocator<'a> {
+	target: f64,
+	metric: psi::PsiMetric,
+	last_adjustment: Instant,
+	chunks: Chunks,
+	provider: &'a dyn MemInfoProvider,
+}
+
+impl<'a> PsiAllocator<'a> {
+	fn new(provider: &'a dyn MemInfoProvider, size: Size, opt: &PsiOpt, chunks: Chunks) -> Self {
+		let target = match size {
+			Size::Percent(percent) => percent as f64,
+			Size::Bytes(_) | Size::Limit(..) => {
+				eprintln!("Allocation mode psi requires the size as stall percentage, e.g. 20%");
+				process::exit(1);
+			}
+		};
+		if let Err(e) = provider.pressure() {
+			eprintln!("Failed to read memory pressure: {}", e);
+			process::exit(1);
+		}
+		println!("Allocate until memory pressure ({} avg10) is at {}%", opt.psi_metric, target);
+		return Self { target, metric: opt.psi_metric, last_adjustment: Instant::now() - PSI_ADJUST_INTERVAL, chunks, provider };
+	}
+}
+
+impl Allocator for PsiAllocator<'_> {
+	fn update(&mut self) {
+		self.chunks.check();
+		// avg10 reacts slowly, give it time to follow the last adjustment
+		if self.last_adjustment.elapsed() < PSI_ADJUST_INTERVAL {
+			return;
+		}
+		self.last_adjustment = Instant::now();
+
+		let stall = match self.provider.pressure() {
+			Ok(pressure) => pressure.avg10(self.metric),
+			Err(e) => {
+				eprintln!("Failed to read memory pressure: {}", e);
+				return;
+			}
+		};
+		let tolerance = (self.target * 0.1).max(1.0);
+		if stall < self.target - tolerance {
+			let available = self.provider.mem_info().available as i64;
+			self.chunks.adjust_by((available / 10).max(2 * MB))
+		} else if stall > self.target + tolerance {
+			let size = self.chunks.size() as i64;
+			self.chunks.adjust_by(-(size / 20).max(2 * MB).min(size))
+		}
+	}
+
+	fn into_chunks(self: Box<Self>) -> Chunks {
+		self.chunks
+	}
+
+	fn chunks(&self) -> &Chunks {
+		&self.chunks
+	}
+}
+
+const SWAP_ADJUST_INTERVAL: Duration = Duration::from_secs(2);
+// adjustments without swap usage growing before giving up, e.g. with swappiness 0
+const SWAP_MAX_STALLED_STEPS: u32 = 5;
+
+struct SwapAllocator<'a> {
+	swap_bytes: usize,
+	// never allocate more than fits into memory and the targeted swap
+	cap: usize,
+	last_swap_used: usize,
+	stalled_steps: u32,
+	last_adjustment: Instant,
+	chunks: Chunks,
+	provider: &'a dyn MemInfoProvider,
+}
+
+impl<'a> SwapAllocator<'a> {
+	fn new(provider: &'a dyn MemInfoProvider, size: Size, chunks: Chunks) -> Self {
+		let mem = provider.mem_info();
+		if mem.swap_total == 0 {
+			eprintln!("Allocation mode swap requires swap to be enabled");
+			process::exit(1);
+		}
+		let swap_bytes = match size {
+			Size::Percent(percent) => (mem.swap_total as f64 * percent as f64 / 100.0) as usize,
+			_ => absolute_bytes(&mem, size).0,
+		}.min(mem.swap_total);
+		let swap_percent = (swap_bytes as f64 / mem.swap_total as f64 * 100.0).round() as u16;
+		println!("Allocate until {} ({}% of total swap) swap is used", bytes_to_string_usize(swap_bytes), swap_percent);
+		let cap = mem.total + swap_bytes;
+		return Self { swap_bytes, cap, last_swap_used: mem.swap_used, stalled_steps: 0, last_adjustment: Instant::now() - SWAP_ADJUST_INTERVAL, chunks, provider };
+	}
+}
+
+impl Allocator for SwapAllocator<'_> {
+	fn update(&mut self) {
+		self.chunks.check();
+		// swapping out lags behind the allocation, give the kernel time to catch up
+		if self.last_adjustment.elapsed() < SWAP_ADJUST_INTERVAL {
+			return;
+		}
+		self.last_adjustment = Instant::now();
+
+		let mem = self.provider.mem_info();
+		let diff = self.swap_bytes as i64 - mem.swap_used as i64;
+		if diff > 0 {
+			if mem.swap_used < self.last_swap_used + MB as usize {
+				self.stalled_steps += 1;
+			} else {
+				self.stalled_steps = 0;
+			}
+			self.last_swap_used = mem.swap_used;
+			if self.stalled_steps >= SWAP_MAX_STALLED_STEPS {
+				if self.stalled_steps == SWAP_MAX_STALLED_STEPS {
+					eprintln!("Swap usage does not grow, stopping at {} allocated", bytes_to_string_usize(self.chunks.size()));
+				}
+				return;
+			}
+			// fill up the available memory and push half of the missing amount into swap
+			let grow = (mem.available as i64 + diff / 2).min(self.cap as i64 - self.chunks.size() as i64);
+			if grow > 0 {
+				self.chunks.adjust_by(grow)
+			}
+		} else if -diff > 2 * MB {
+			self.chunks.adjust_by(diff)
+		}
+	}
+
+	fn into_chunks(self: Box<Self>) -> Chunks {
+		self.chunks
+	}
+
+	fn chunks(&self) -> &Chunks {
+		&self.chunks
 	}
 }
 
 struct Chunks {
 	chunks: Vec<Chunk>,
 	last_allocation: Instant,
+	last_failure: Option<Instant>,
+	opt: ChunkOpt,
 }
 
 const KB: i64 = 1024;
 const MB: i64 = 1024 * KB;
 
 impl Chunks {
-	fn new() -> Self {
-		return Self { chunks: vec![], last_allocation: Instant::now() };
+	fn new(opt: ChunkOpt) -> Self {
+		return Self { chunks: vec![], last_allocation: Instant::now(), last_failure: None, opt };
 	}
 
 	fn size(&self) -> usize {
@@ -208,7 +1022,18 @@ impl Chunks {
 	}
 
 	fn check(&mut self) {
-		self.chunks.iter_mut().for_each(|c| { c.check() });
+		let opt = &self.opt;
+		self.chunks.iter_mut().for_each(|c| { c.check(opt) });
+	}
+
+	fn bandwidth(&self) -> u64 {
+		self.chunks.iter().map(|c| { c.bandwidth() }).sum()
+	}
+
+	fn clear(&mut self) {
+		while let Some(mut c) = self.chunks.pop() {
+			c.free();
+		}
 	}
 
 	fn resize(&mut self, size: usize) {
@@ -224,6 +1049,11 @@ impl Chunks {
 		self.last_allocation = now;
 		let mut freed = 0;
 		while freed < -size {
+			// chunks within memfill give back just the excess instead of being freed completely
+			if let Some(shrunk) = self.chunks.last_mut().and_then(|c| c.shrink((-size - freed) as usize, &self.opt)) {
+				freed += shrunk as i64;
+				break;
+			}
 			match self.chunks.pop() {
 				None => { break; }
 				Some(mut c) => {
@@ -232,148 +1062,734 @@ impl Chunks {
 			}
 		}
 		let allocate = freed + size;
-		if allocate > 0 {
+		if allocate > 0 && !self.failed_recently() {
 			let count = if allocate < (16 * MB) { 1 } else { 2.max(allocate / (1024 * MB)) };
+			// whole huge pages only, rounding up would exceed the target and free the chunk again
+			let chunk = (allocate / count) as usize;
+			let chunk = self.opt.huge_page_size().map_or(chunk, |page| chunk / page * page);
 			for _i in 0..count {
-				self.chunks.push(Chunk::new((allocate / count) as usize))
+				if chunk > 0 {
+					self.allocate(chunk)
+				}
 			}
 		}
 	}
+
+	fn push(&mut self, size: usize) {
+		self.last_allocation = Instant::now();
+		if !self.failed_recently() {
+			self.allocate(size)
+		}
+	}
+
+	fn allocate(&mut self, size: usize) {
+		let chunk = Chunk::new(size, &self.opt);
+		if chunk.size() == 0 && size > 0 {
+			self.last_failure = Some(Instant::now());
+		} else {
+			self.chunks.push(chunk)
+		}
+	}
+
+	// back off after a failed allocation instead of retrying on every update
+	fn failed_recently(&self) -> bool {
+		self.last_failure.is_some_and(|f| f.elapsed() < Duration::from_secs(1))
+	}
 }
 
 struct Chunk {
 	size: usize,
-	pid: Pid,
+	holder: Holder,
+	bandwidth: u64,
 }
 
-extern "C" fn sig_cont(_: libc::c_int, _: *mut libc::siginfo_t, _: *mut libc::c_void) {
-	//NOOP
+enum Holder {
+	None,
+	Process(Pid, OwnedFd),
+	Thread(worker::Worker),
+	Region(region::Region),
+	File(PathBuf, bool),
+	Memfd(OwnedFd),
+	Kernel(kmem::KernelObjects),
+	// start of the last rewrite and how far it got
+	Dirty(PathBuf, fs::File, Instant, usize),
 }
 
-const PID_ZERO: Pid = Pid::from_raw(0);
+// bytes of a dirty file rewritten per check
+const REDIRTY_SLICE: usize = 16 * MB as usize;
+
+static STOPPED: AtomicBool = AtomicBool::new(false);
+static TERMINATED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn sig_term(_: libc::c_int, _: *mut libc::siginfo_t, _: *mut libc::c_void) {
+	TERMINATED.store(true, Ordering::SeqCst);
+}
+
+// memfill releases its chunks before exiting, e.g. to remove files.
+// blocking calls are restarted, the main loop notices the flag
+fn install_termination_handler() {
+	let action = SigAction::new(SigHandler::SigAction(sig_term), SaFlags::SA_RESTART, SigSet::empty());
+	unsafe {
+		sigaction(Signal::SIGINT, &action).unwrap();
+		sigaction(Signal::SIGTERM, &action).unwrap();
+	}
+}
+
+fn reset_termination_handler() {
+	let action = SigAction::new(SigHandler::SigDfl, SaFlags::empty(), SigSet::empty());
+	unsafe {
+		sigaction(Signal::SIGINT, &action).unwrap();
+		sigaction(Signal::SIGTERM, &action).unwrap();
+	}
+}
+
+extern "C" fn sig_cont(_: libc::c_int, _: *mut libc::siginfo_t, _: *mut libc::c_void) {
+	STOPPED.store(true, Ordering::SeqCst);
+}
 
 impl Chunk {
-	fn new(size: usize) -> Self {
-		if size <= 0 {
-			return Self { size, pid: PID_ZERO };
+	fn new(size: usize, opt: &ChunkOpt) -> Self {
+		if size == 0 {
+			return Self { size, holder: Holder::None, bandwidth: 0 };
+		}
+
+		// the chunk accounts for the whole huge pages backing it
+		let size = opt.huge_page_size().map_or(size, |page| size.next_multiple_of(page));
+		match opt.backend {
+			Backend::Process => Self::new_process(size, opt),
+			Backend::Thread => Self::new_thread(size, opt),
+			Backend::Mmap => Self::new_region(size, opt),
+			Backend::PageCache => Self::new_file(size, opt),
+			Backend::Shm => Self::new_shm_file(size, opt),
+			Backend::Memfd => Self::new_memfd(size, opt),
+			Backend::Kmem => Self::new_kmem(size, opt),
+			Backend::Dirty => Self::new_dirty_file(size, opt),
 		}
+	}
 
-		let pipe = unistd::pipe().unwrap();
+	fn new_process(size: usize, opt: &ChunkOpt) -> Self {
+		let (reader, writer) = unistd::pipe().unwrap();
 
 		match unsafe { fork() } {
 			Ok(ForkResult::Child) => unsafe {
 				prctl::set_pdeathsig(Some(Signal::SIGTERM)).unwrap();
+				reset_termination_handler();
 				sigaction(SIGCONT, &SigAction::new(SigHandler::SigAction(sig_cont), SaFlags::empty(), SigSet::empty())).unwrap();
 
-				let layout = Layout::array::<u8>(size).unwrap();
-				let ptr = alloc(layout);
-				if ptr.is_null() {
-					panic!("[{}] Failed to allocate memory", process::id());
+				// join while the host cgroup path is still visible, the mount namespace may hide it
+				if let Some(dir) = &opt.join_cgroup {
+					if let Err(e) = cgroup::join(dir, process::id()) {
+						eprintln!("[{}] Failed to join cgroup: {:?}", process::id(), e);
+						process::exit(1);
+					}
+				}
+				if let Some(pid) = opt.mount_namespace {
+					if let Err(e) = namespace::enter_mount_namespace(pid) {
+						eprintln!("[{}] Failed to enter mount namespace: {}", process::id(), e);
+						process::exit(1);
+					}
+				}
+
+				// when fragmenting a larger region is filled, of which size remains allocated
+				let len = opt.fragment.map_or(size, |f| f.region_len(size, page_size()));
+				let region = match region::Region::new(len, opt.pages) {
+					Ok(region) => region,
+					Err(e) => {
+						eprintln!("[{}] Failed to allocate memory: {}", process::id(), e);
+						process::exit(1);
+					}
+				};
+				let ptr = region.as_ptr();
+				if let Some(policy) = opt.numa_policy() {
+					if let Err(e) = policy.bind(ptr, len) {
+						eprintln!("[{}] Failed to bind memory to numa nodes: {}", process::id(), e);
+						process::exit(1);
+					}
+				}
+				if opt.ksm {
+					if let Err(e) = region.advise(MmapAdvise::MADV_MERGEABLE) {
+						eprintln!("[{}] Failed to mark memory as mergeable: {}", process::id(), e);
+					}
 				}
 
-				let rand = rand::random();
-				write_bytes(ptr, rand, size);
-				let mut res = 0u8;
-				copy(ptr, &mut res, 1);
-				if res != rand {
-					panic!("[{}] Memory pattern assertion failed", process::id());
+				pattern::Filler::new(opt.pattern, page_size()).fill(slice::from_raw_parts_mut(ptr, len));
+				if let Some(fragment) = opt.fragment {
+					if let Err(e) = fragment.fragment(&region, len, page_size()) {
+						eprintln!("[{}] Failed to fragment memory: {}", process::id(), e);
+						process::exit(1);
+					}
+				}
+				if opt.mlock {
+					if let Err(e) = region.lock() {
+						eprintln!("[{}] Failed to lock memory: {}", process::id(), e);
+						process::exit(1);
+					}
 				}
 				println!("[{}] Allocated {}", process::id(), bytes_to_string_usize(size));
 
-				unistd::write(pipe.1.as_fd(), "0".as_bytes()).unwrap();
-				unistd::pause();
+				unistd::write(writer.as_fd(), "0".as_bytes()).unwrap();
+				touch::keep_active(ptr, size, opt, || STOPPED.load(Ordering::SeqCst), |interval| match interval {
+					Some(interval) => interruptible_sleep(interval),
+					None => unistd::pause(),
+				}, |throughput| {
+					let _ = unistd::write(writer.as_fd(), &throughput.to_le_bytes());
+				});
 
-				dealloc(ptr, layout);
+				drop(region);
 
 				process::exit(0);
 			}
 
 			Ok(ForkResult::Parent { child, .. }) => {
-				let buf =&mut [0u8];
-				unistd::read(pipe.0.as_fd().as_raw_fd(), buf).unwrap();
-				Self { size, pid: child }
+				drop(writer);
+				let buf = &mut [0u8];
+				let ready = loop {
+					match unistd::read(reader.as_raw_fd(), buf) {
+						Err(Errno::EINTR) => continue,
+						result => break result,
+					}
+				};
+				if ready != Ok(1) {
+					// child exited before it allocated the memory, make sure it doesn't linger either way
+					let _ = signal::kill(child, Signal::SIGTERM);
+					let _ = waitpid(child, None);
+					return Self { size: 0, holder: Holder::None, bandwidth: 0 };
+				}
+				fcntl(reader.as_raw_fd(), FcntlArg::F_SETFL(OFlag::O_NONBLOCK)).unwrap();
+				Self { size, holder: Holder::Process(child, reader), bandwidth: 0 }
 			}
 
 			Err(e) => {
 				eprintln!("Fork failed: {}", e);
-				Self { size: 0, pid: PID_ZERO }
+				Self { size: 0, holder: Holder::None, bandwidth: 0 }
+			}
+		}
+	}
+
+	fn new_thread(size: usize, opt: &ChunkOpt) -> Self {
+		match worker::Worker::start(size, opt) {
+			Ok(worker) => {
+				println!("[{}] Allocated {}", worker.name(), bytes_to_string_usize(size));
+				Self { size, holder: Holder::Thread(worker), bandwidth: 0 }
+			}
+			Err(e) => {
+				eprintln!("Failed to allocate memory: {}", e);
+				Self { size: 0, holder: Holder::None, bandwidth: 0 }
+			}
+		}
+	}
+
+	fn new_region(size: usize, opt: &ChunkOpt) -> Self {
+		match Self::fill_region(size, opt) {
+			Ok(region) => {
+				println!("[{:p}] Allocated {}", region.as_ptr(), bytes_to_string_usize(size));
+				Self { size, holder: Holder::Region(region), bandwidth: 0 }
+			}
+			Err(e) => {
+				eprintln!("Failed to allocate memory: {}", e);
+				Self { size: 0, holder: Holder::None, bandwidth: 0 }
+			}
+		}
+	}
+
+	// memfill's memory policy already binds the region to the numa nodes
+	fn fill_region(size: usize, opt: &ChunkOpt) -> Result<region::Region, String> {
+		let region = region::Region::new(size, opt.pages)?;
+		if opt.ksm {
+			if let Err(e) = region.advise(MmapAdvise::MADV_MERGEABLE) {
+				eprintln!("Failed to mark memory as mergeable: {}", e);
+			}
+		}
+		pattern::Filler::new(opt.pattern, page_size()).fill(unsafe { slice::from_raw_parts_mut(region.as_ptr(), size) });
+		if opt.mlock {
+			region.lock()?;
+		}
+		Ok(region)
+	}
+
+	// gives back the pages at the end of the region, None if the chunk has to be freed completely
+	fn shrink(&mut self, size: usize, opt: &ChunkOpt) -> Option<usize> {
+		let Holder::Region(region) = &self.holder else { return None };
+		// locked and huge pages can't be dropped page by page
+		if opt.mlock || opt.huge_page_size().is_some() {
+			return None;
+		}
+		let keep = self.size.saturating_sub(size).next_multiple_of(page_size());
+		if keep == 0 || keep >= self.size {
+			return None;
+		}
+		if let Err(e) = region.advise_range(keep, self.size - keep, MmapAdvise::MADV_DONTNEED) {
+			eprintln!("Failed to shrink memory: {}", e);
+			return None;
+		}
+		let shrunk = self.size - keep;
+		println!("[{:p}] De-allocated {}", region.as_ptr(), bytes_to_string_usize(shrunk));
+		self.size = keep;
+		Some(shrunk)
+	}
+
+	fn new_file(size: usize, opt: &ChunkOpt) -> Self {
+		let path = pagecache::new_file_path(&opt.file_dir());
+		match pagecache::fill(&path, size, opt.pattern, opt.fadvise) {
+			Ok(_) => {
+				println!("[{}] Cached {}", path.display(), bytes_to_string_usize(size));
+				Self { size, holder: Holder::File(path, opt.keep_files), bandwidth: 0 }
+			}
+			Err(e) => {
+				eprintln!("Failed to fill page cache: {}", e);
+				Self { size: 0, holder: Holder::None, bandwidth: 0 }
+			}
+		}
+	}
+
+	fn new_shm_file(size: usize, opt: &ChunkOpt) -> Self {
+		let path = pagecache::new_file_path(&opt.file_dir());
+		match shm::fill_file(&path, size, opt.pattern) {
+			Ok(_) => {
+				println!("[{}] Allocated {}", path.display(), bytes_to_string_usize(size));
+				Self { size, holder: Holder::File(path, false), bandwidth: 0 }
+			}
+			Err(e) => {
+				eprintln!("Failed to fill shared memory: {}", e);
+				Self { size: 0, holder: Holder::None, bandwidth: 0 }
+			}
+		}
+	}
+
+	fn new_memfd(size: usize, opt: &ChunkOpt) -> Self {
+		match shm::fill_memfd(size, opt.pattern) {
+			Ok(fd) => {
+				println!("[memfd:{}] Allocated {}", fd.as_raw_fd(), bytes_to_string_usize(size));
+				Self { size, holder: Holder::Memfd(fd), bandwidth: 0 }
+			}
+			Err(e) => {
+				eprintln!("Failed to fill shared memory: {}", e);
+				Self { size: 0, holder: Holder::None, bandwidth: 0 }
+			}
+		}
+	}
+
+	fn new_dirty_file(size: usize, opt: &ChunkOpt) -> Self {
+		let path = pagecache::new_file_path(&opt.file_dir());
+		match pagecache::fill_dirty(&path, size, opt.pattern) {
+			Ok(file) => {
+				println!("[{}] Dirtied {}", path.display(), bytes_to_string_usize(size));
+				Self { size, holder: Holder::Dirty(path, file, Instant::now(), size), bandwidth: 0 }
+			}
+			Err(e) => {
+				eprintln!("Failed to dirty page cache: {}", e);
+				Self { size: 0, holder: Holder::None, bandwidth: 0 }
+			}
+		}
+	}
+
+	fn new_kmem(size: usize, opt: &ChunkOpt) -> Self {
+		match kmem::fill(opt.kmem_objects, &opt.file_dir(), size) {
+			Ok((objects, size)) => {
+				println!("[kmem:{:?}] Allocated {}", opt.kmem_objects, bytes_to_string_usize(size));
+				Self { size, holder: Holder::Kernel(objects), bandwidth: 0 }
+			}
+			Err(e) => {
+				eprintln!("Failed to allocate kernel memory: {}", e);
+				Self { size: 0, holder: Holder::None, bandwidth: 0 }
 			}
 		}
 	}
 
 	fn size(&self) -> usize {
-		if self.pid == PID_ZERO {
-			0
-		} else {
-			self.size
+		match self.holder {
+			Holder::None => 0,
+			_ => self.size,
 		}
 	}
 
-	fn check(&mut self) {
-		self.wait(Some(WaitPidFlag::WNOHANG));
+	fn bandwidth(&self) -> u64 {
+		match &self.holder {
+			Holder::Process(..) => self.bandwidth,
+			Holder::Thread(worker) => worker.bandwidth(),
+			_ => 0,
+		}
+	}
+
+	fn check(&mut self, opt: &ChunkOpt) {
+		match &mut self.holder {
+			Holder::Process(..) => {
+				self.read_reports();
+				self.wait(Some(WaitPidFlag::WNOHANG));
+			}
+			// a slice per check, rewriting large files at once would stall the main loop
+			Holder::Dirty(path, file, last_write, written) if *written < self.size || last_write.elapsed() >= opt.dirty_interval => {
+				if *written >= self.size {
+					*last_write = Instant::now();
+					*written = 0;
+				}
+				let len = REDIRTY_SLICE.min(self.size - *written);
+				match pagecache::redirty(file, *written, len, opt.pattern) {
+					Ok(_) => *written += len,
+					Err(e) => {
+						eprintln!("Failed to dirty page cache: {}: {}", path.display(), e);
+						// try again with the next interval
+						*written = self.size;
+					}
+				}
+			}
+			_ => {}
+		}
+	}
+
+	fn read_reports(&mut self) {
+		if let Holder::Process(_, reports) = &self.holder {
+			let buf = &mut [0u8; 8];
+			while let Ok(8) = unistd::read(reports.as_raw_fd(), buf) {
+				self.bandwidth = u64::from_le_bytes(*buf);
+			}
+		}
 	}
 
 	fn wait(&mut self, option: Option<WaitPidFlag>) {
-		match waitpid(self.pid, option) {
+		let Holder::Process(pid, _) = self.holder else { return };
+		match waitpid(pid, option) {
 			Ok(WaitStatus::Exited(pid, code)) => {
 				println!("[{}] Exited({}) and de-allocated {}", pid, code, bytes_to_string_usize(self.size));
-				self.pid = PID_ZERO;
+				self.holder = Holder::None;
 			}
 			Ok(WaitStatus::Signaled(pid, signal, _)) => {
 				println!("[{}] Killed by {} and de-allocated {}", pid, signal, bytes_to_string_usize(self.size));
-				self.pid = PID_ZERO;
+				self.holder = Holder::None;
 			}
 			Ok(_) => {}
-			Err(Errno::ECHILD) => { self.pid = PID_ZERO; }
+			Err(Errno::ECHILD) => { self.holder = Holder::None; }
 			Err(e) => {
-				println!("[{}] errno: {} ", self.pid, e);
-				self.pid = PID_ZERO;
+				println!("[{}] errno: {} ", pid, e);
+				self.holder = Holder::None;
 			}
 		}
 	}
 
 	fn free(&mut self) -> usize {
-		if self.pid != PID_ZERO {
-			signal::kill(self.pid, SIGCONT).unwrap();
-			self.wait(None);
-			self.size
-		} else {
-			0
+		match &self.holder {
+			Holder::None => 0,
+			Holder::Process(pid, _) => {
+				signal::kill(*pid, SIGCONT).unwrap();
+				self.wait(None);
+				self.size
+			}
+			Holder::File(path, keep) => {
+				match pagecache::release(path, *keep) {
+					Ok(_) => println!("[{}] Released {}", path.display(), bytes_to_string_usize(self.size)),
+					Err(e) => eprintln!("Failed to release file: {}", e),
+				}
+				self.holder = Holder::None;
+				self.size
+			}
+			Holder::Memfd(fd) => {
+				println!("[memfd:{}] Released {}", fd.as_raw_fd(), bytes_to_string_usize(self.size));
+				self.holder = Holder::None;
+				self.size
+			}
+			Holder::Thread(_) => {
+				let Holder::Thread(worker) = std::mem::replace(&mut self.holder, Holder::None) else { unreachable!() };
+				println!("[{}] De-allocated {}", worker.name(), bytes_to_string_usize(self.size));
+				worker.stop();
+				self.size
+			}
+			Holder::Region(region) => {
+				println!("[{:p}] De-allocated {}", region.as_ptr(), bytes_to_string_usize(self.size));
+				// dropping the region unmaps it
+				self.holder = Holder::None;
+				self.size
+			}
+			Holder::Dirty(path, ..) => {
+				// unlinking discards the dirty pages without writing them back
+				match fs::remove_file(path) {
+					Ok(_) => println!("[{}] Released {}", path.display(), bytes_to_string_usize(self.size)),
+					Err(e) => eprintln!("Failed to release file: {}: {}", path.display(), e),
+				}
+				self.holder = Holder::None;
+				self.size
+			}
+			Holder::Kernel(_) => {
+				let Holder::Kernel(objects) = std::mem::replace(&mut self.holder, Holder::None) else { unreachable!() };
+				match kmem::release(objects) {
+					Ok(_) => println!("[kmem] Released {}", bytes_to_string_usize(self.size)),
+					Err(e) => eprintln!("Failed to release kernel memory: {}", e),
+				}
+				self.size
+			}
 		}
 	}
 }
 
 fn main() {
-	let opts = Opt::from_args();
+	let mut opts = Opt::from_args();
 	adjust_oom_score();
+	install_termination_handler();
 
-	let mem_info: Box<dyn MemInfoProvider> = if opts.ignore_cgroup {
+	let target = match (&opts.cgroup, opts.target_pid) {
+		(Some(path), _) => cgroup::CgroupTarget::Path(path.clone()),
+		(_, Some(pid)) => cgroup::CgroupTarget::Pid(pid),
+		_ => cgroup::CgroupTarget::Own,
+	};
+	let numa_policy = opts.chunk_opt.numa_policy();
+	let mem_info: Box<dyn MemInfoProvider> = if let Some(policy) = &numa_policy {
+		if let Some(e) = policy.nodes().0.iter().find_map(|node| numa::read_node_meminfo(*node).err()) {
+			eprintln!("Failed to read numa node memory: {}", e);
+			process::exit(1);
+		}
+		Box::new(NumaMemInfo { nodes: policy.nodes().clone() })
+	} else if opts.ignore_cgroup {
 		Box::new(SystemMemInfo {})
 	} else {
-		Box::new(CgroupMemInfo {})
+		if let Err(e) = cgroup::read_cgroup_memory(&target) {
+			eprintln!("Failed to read cgroup memory: {:?}", e);
+			process::exit(1);
+		}
+		Box::new(CgroupMemInfo { target: target.clone() })
 	};
 
-	let mut allocator = new_allocator(opts.alloc_mode, mem_info.as_ref(), opts.size);
-	println!("Terminating after {}s", opts.duration.as_secs());
-	let deadline = Instant::now() + opts.duration;
+	let join_cgroup = match (&opts.cgroup, opts.join, opts.target_pid) {
+		(Some(dir), true, _) => Some(dir.clone()),
+		(_, _, Some(pid)) => Some(cgroup::cgroup_dir(&cgroup::CgroupTarget::Pid(pid)).unwrap_or_else(|e| {
+			eprintln!("Failed to find cgroup of {}: {:?}", pid, e);
+			process::exit(1);
+		})),
+		_ => None,
+	};
+	if opts.sub_cgroup.is_some() && !matches!(opts.chunk_opt.backend, Backend::Process) {
+		eprintln!("--sub-cgroup requires the process backend");
+		process::exit(1);
+	}
+	if opts.chunk_opt.fragment.is_some() && !matches!(opts.chunk_opt.backend, Backend::Process) {
+		eprintln!("--fragment requires the process backend");
+		process::exit(1);
+	}
+	// madvise can't free single pages of a hugetlb page
+	if opts.chunk_opt.fragment.is_some() && opts.chunk_opt.huge_page_size().is_some() {
+		eprintln!("--fragment can't be combined with hugetlb pages");
+		process::exit(1);
+	}
+	if let Some(dir) = join_cgroup {
+		match opts.chunk_opt.backend {
+			Backend::Process => opts.chunk_opt.join_cgroup = Some(dir),
+			// the chunks live within memfill
+			_ => if let Err(e) = cgroup::join(&dir, process::id()) {
+				eprintln!("Failed to join cgroup: {:?}", e);
+				process::exit(1);
+			}
+		}
+	}
+
+	if let (Some(pid), true) = (opts.target_pid, opts.enter_namespaces) {
+		match opts.chunk_opt.backend {
+			Backend::Process => {
+				if let Err(e) = namespace::enter_pid_namespace(pid) {
+					eprintln!("Failed to enter pid namespace: {}", e);
+					process::exit(1);
+				}
+				opts.chunk_opt.mount_namespace = Some(pid);
+			}
+			// memfill can't enter the mount namespace itself as it still reads the cgroup from the host
+			_ => opts.chunk_opt.root = Some(PathBuf::from(format!("/proc/{}/root", pid))),
+		}
+	}
+
+	// all but the process backend allocate within memfill itself
+	if let (Some(policy), false) = (&numa_policy, matches!(opts.chunk_opt.backend, Backend::Process)) {
+		if let Err(e) = policy.apply() {
+			eprintln!("Failed to set numa memory policy: {}", e);
+			process::exit(1);
+		}
+	}
+
+	let phases = match opts.scenario.take() {
+		Some(path) => scenario::read_scenario(path).unwrap_or_else(|e| {
+			eprintln!("Failed to read scenario: {}", e);
+			process::exit(1);
+		}).phases,
+		None => vec![scenario::Phase {
+			size: opts.size.unwrap(),
+			mode: opts.alloc_mode.unwrap(),
+			duration: opts.duration.unwrap(),
+			release: false,
+			mode_opt: mem::take(&mut opts.mode_opt),
+		}],
+	};
+
+	for (i, phase) in phases.iter().enumerate() {
+		if let Err(e) = check_phase(phase, mem_info.as_ref()) {
+			match phases.len() {
+				1 => eprintln!("{}", e),
+				_ => eprintln!("Phase {}: {}", i + 1, e),
+			}
+			process::exit(1);
+		}
+	}
+
+	// created last, so that no validation above leaves it behind
+	let sub_cgroup = opts.sub_cgroup.as_ref().map(|name| {
+		let sub_cgroup = create_sub_cgroup(&target, name, &opts).unwrap_or_else(|e| {
+			match e {
+				cgroup::CGroupError::Populated(parent) => eprintln!("Failed to create cgroup {}: {} has processes, the memory controller can't be enabled for its children; \
+					memfill's own cgroup and that of --target-pid usually have processes, pass a --cgroup without processes of its own, e.g. a slice, or the root cgroup", name, parent.display()),
+				e => eprintln!("Failed to create cgroup {}: {:?}", name, e),
+			}
+			process::exit(1);
+		});
+		println!("Created cgroup {}", sub_cgroup.path.display());
+		sub_cgroup
+	});
+	if let Some(child) = &sub_cgroup {
+		opts.chunk_opt.join_cgroup = Some(child.path.clone());
+	}
+
+	let report = Report {
+		kernel_baseline: match opts.chunk_opt.backend {
+			Backend::Kmem => {
+				kmem::raise_fd_limit();
+				kmem::KernelMemory::read(mem_info.memory_stat().as_ref()).ok()
+			}
+			_ => None,
+		},
+		sub_cgroup,
+	};
+
+	if opts.chunk_opt.ksm && !ksm::is_running() {
+		eprintln!("ksm is not running, enable it with: echo 1 > /sys/kernel/mm/ksm/run");
+	}
+	if opts.chunk_opt.fragment.is_some() {
+		fragment::print_free_pages("before");
+	}
+	let mut chunks = Chunks::new(opts.chunk_opt);
+	for (i, phase) in phases.iter().enumerate() {
+		if phases.len() > 1 {
+			println!("Phase {}/{}: {:?} for {}s", i + 1, phases.len(), phase.mode, phase.duration.as_secs());
+		}
+		let mut allocator = new_allocator(phase, mem_info.as_ref(), chunks);
+		println!("Terminating after {}s", phase.duration.as_secs());
+		run(allocator.as_mut(), mem_info.as_ref(), phase.duration, &report);
+		chunks = allocator.into_chunks();
+		if phase.release {
+			chunks.clear();
+		}
+		if TERMINATED.load(Ordering::SeqCst) {
+			break;
+		}
+	}
+	chunks.clear();
+	if let Some(child) = &report.sub_cgroup {
+		match child.remove() {
+			Ok(_) => println!("Removed cgroup {}", child.path.display()),
+			Err(e) => eprintln!("Failed to remove cgroup: {:?}", e),
+		}
+	}
+}
+
+fn create_sub_cgroup(target: &cgroup::CgroupTarget, name: &str, opts: &Opt) -> Result<cgroup::ChildCgroup, cgroup::CGroupError> {
+	let child = cgroup::create_child(&cgroup::cgroup_dir(target)?, name)?;
+	let limits = [("memory.max", opts.sub_cgroup_max), ("memory.high", opts.sub_cgroup_high)];
+	let settings = limits.iter().filter_map(|(file, size)| size.map(|s| (*file, s.as_u64().to_string())))
+		.chain(opts.sub_cgroup_oom_group.then(|| ("memory.oom.group", "1".to_string())));
+	for (file, value) in settings {
+		if let Err(e) = cgroup::write_value(&child.path, file, &value) {
+			let _ = child.remove();
+			return Err(e);
+		}
+	}
+	Ok(child)
+}
+
+// additional information logged while running
+struct Report {
+	// kernel memory before memfill started, to report its growth
+	kernel_baseline: Option<kmem::KernelMemory>,
+	// cgroup created for the chunk processes, its usage is cross-checked against the allocated size
+	sub_cgroup: Option<cgroup::ChildCgroup>,
+}
+
+fn run(allocator: &mut dyn Allocator, mem_info: &dyn MemInfoProvider, duration: Duration, report: &Report) {
+	let deadline = Instant::now() + duration;
 	let mut last_log = Instant::now() - Duration::from_secs(5);
-	while Instant::now() < deadline {
+	let mut fragmented = allocator.chunks().opt.fragment.is_none();
+	while Instant::now() < deadline && !TERMINATED.load(Ordering::SeqCst) {
 		allocator.update();
 
+		// the free pages right after fragmenting, before the kernel compacts them again
+		if !fragmented && allocator.target().is_some_and(|target| allocator.size() + 2 * MB as usize >= target) {
+			fragment::print_free_pages("after");
+			fragmented = true;
+		}
+
 		let now = Instant::now();
 		if now - last_log > Duration::from_secs(5) {
 			let mem = mem_info.mem_info();
 			print!("Available memory: {} ({}% of total memory); ", bytes_to_string_usize(mem.available), (mem.available as f64 / mem.total as f64 * 100.0).round() as i16);
 			print!("Allocated by memfill: {} ({}% of total memory)", bytes_to_string_usize(allocator.size()), (allocator.size() as f64 / mem.total as f64 * 100.0).round() as i16);
+			if allocator.chunks().bandwidth() > 0 {
+				print!("; Bandwidth: {}/s", bytes_to_string_usize(allocator.chunks().bandwidth() as usize));
+			}
+			if allocator.chunks().opt.ksm {
+				if let Ok(pages) = ksm::pages_sharing() {
+					print!("; Saved by ksm: {}", bytes_to_string_usize(pages * page_size()));
+				}
+			}
+			if let Some(before) = &report.kernel_baseline {
+				if let Ok(now) = kmem::KernelMemory::read(mem_info.memory_stat().as_ref()) {
+					print!("; Slab: {} ({}), reclaimable: {} ({}), unreclaimable: {} ({})",
+						bytes_to_string_usize(now.slab), bytes_change(now.slab, before.slab),
+						bytes_to_string_usize(now.slab_reclaimable), bytes_change(now.slab_reclaimable, before.slab_reclaimable),
+						bytes_to_string_usize(now.slab_unreclaimable), bytes_change(now.slab_unreclaimable, before.slab_unreclaimable));
+					if let (Some(now), Some(before)) = (now.cgroup_kernel, before.cgroup_kernel) {
+						print!("; Cgroup kernel: {} ({})", bytes_to_string_usize(now), bytes_change(now, before));
+					}
+					if let (Some(now), Some(before)) = (now.cgroup_sock, before.cgroup_sock) {
+						print!("; Cgroup sock: {} ({})", bytes_to_string_usize(now), bytes_change(now, before));
+					}
+				}
+			}
+			if let Backend::Dirty = allocator.chunks().opt.backend {
+				if let Ok(dirty) = pagecache::DirtyMemory::read(mem_info.memory_stat().as_ref()) {
+					print!("; Dirty: {}, Writeback: {}", bytes_to_string_usize(dirty.dirty), bytes_to_string_usize(dirty.writeback));
+					if let (Some(file_dirty), Some(file_writeback)) = (dirty.cgroup_dirty, dirty.cgroup_writeback) {
+						print!("; Cgroup file_dirty: {}, file_writeback: {}", bytes_to_string_usize(file_dirty), bytes_to_string_usize(file_writeback));
+					}
+				}
+			}
+			if let Some(child) = &report.sub_cgroup {
+				if let Ok(usage) = cgroup::read_memory_current(&child.path) {
+					print!("; Sub-cgroup usage: {}", bytes_to_string_usize(usage));
+				}
+			}
+			if mem.swap_total > 0 {
+				print!("; Swap used: {} ({}% of total swap)", bytes_to_string_usize(mem.swap_used), (mem.swap_used as f64 / mem.swap_total as f64 * 100.0).round() as i16);
+			}
+			if let Ok(pressure) = mem_info.pressure() {
+				print!("; Memory pressure avg10: some {:.2}%, full {:.2}%", pressure.some_avg10, pressure.full_avg10);
+			}
 			println!();
 			last_log = now;
 		}
 
 		sleep(Duration::from_millis(50));
 	}
+	if !fragmented {
+		fragment::print_free_pages("after");
+	}
+}
+
+fn page_size() -> usize {
+	match unistd::sysconf(unistd::SysconfVar::PAGE_SIZE) {
+		Ok(Some(ps)) => ps as usize,
+		_ => 4096,
+	}
 }
 
-fn adjust_oom_score() -> () {
+// unlike std::thread::sleep returns early when a signal is caught
+fn interruptible_sleep(duration: Duration) {
+	let ts = libc::timespec { tv_sec: duration.as_secs() as libc::time_t, tv_nsec: duration.subsec_nanos() as libc::c_long };
+	unsafe { libc::nanosleep(&ts, null_mut()) };
+}
+
+fn adjust_oom_score() {
 	let is_privileged = Uid::current().is_root() || Uid::effective().is_root();
 	match fs::write("/proc/self/oom_score_adj", if is_privileged { "-1000" } else { "0" }) {
 		Ok(_) => {}
@@ -382,3 +1798,116 @@ fn adjust_oom_score() -> () {
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn cgroup(limit: usize, unlimited: bool, high: Option<usize>) -> cgroup::CGroupMemory {
+		cgroup::CGroupMemory { usage: 0, limit, unlimited, high, low: None, min: None, swap_usage: None, swap_limit: None }
+	}
+
+	#[test]
+	fn parse_size_absolute_and_percent() {
+		assert!(matches!(parse_size("512M").unwrap(), Size::Bytes(512_000_000)));
+		assert!(matches!(parse_size("1GiB").unwrap(), Size::Bytes(1_073_741_824)));
+		assert!(matches!(parse_size("80%").unwrap(), Size::Percent(80)));
+		assert!(parse_size("80 percent").is_err());
+	}
+
+	#[test]
+	fn parse_size_limit_offsets() {
+		assert!(matches!(parse_size("max").unwrap(), Size::Limit(MemoryLimit::Max, LimitOffset::Bytes(0))));
+		assert!(matches!(parse_size("high+5%").unwrap(), Size::Limit(MemoryLimit::High, LimitOffset::Percent(5))));
+		assert!(matches!(parse_size("max-1G").unwrap(), Size::Limit(MemoryLimit::Max, LimitOffset::Bytes(-1_000_000_000))));
+		assert!(matches!(parse_size("low-10%").unwrap(), Size::Limit(MemoryLimit::Low, LimitOffset::Percent(-10))));
+		assert!(matches!(parse_size("min+100M").unwrap(), Size::Limit(MemoryLimit::Min, LimitOffset::Bytes(100_000_000))));
+	}
+
+	#[test]
+	fn parse_size_invalid_limit_offsets() {
+		assert!(parse_size("max5%").is_err());
+		assert!(parse_size("high+").is_err());
+		assert!(parse_size("max-high").is_err());
+	}
+
+	#[test]
+	fn limit_offsets_relative_to_limit() {
+		let mem = cgroup_mem_info(&cgroup(4_000_000_000, false, Some(2_000_000_000)), 16_000_000_000, 0, 0);
+		assert_eq!(absolute_bytes(&mem, parse_size("high+5%").unwrap()).0, 2_100_000_000);
+		assert_eq!(absolute_bytes(&mem, parse_size("max-1G").unwrap()).0, 3_000_000_000);
+		assert_eq!(absolute_bytes(&mem, parse_size("max-10G").unwrap()).0, 0);
+		assert_eq!(absolute_bytes(&mem, parse_size("max").unwrap()), (4_000_000_000, 100));
+	}
+
+	#[test]
+	fn max_of_unlimited_cgroup_is_system_memory() {
+		let mem = cgroup_mem_info(&cgroup(9_223_372_036_854_771_712, true, None), 16_000_000_000, 0, 0);
+		assert_eq!(absolute_bytes(&mem, parse_size("max").unwrap()), (16_000_000_000, 100));
+		assert_eq!(absolute_bytes(&mem, parse_size("max-1G").unwrap()).0, 15_000_000_000);
+	}
+
+	fn ramp(bytes: usize, up: u64, down: u64, duration: u64, steps: Option<u32>) -> RampAllocator {
+		let start = Instant::now();
+		let chunks = Chunks::new(ChunkOpt::from_iter(["memfill"]));
+		RampAllocator { bytes, initial: 0, start, ramp_up: Duration::from_secs(up), ramp_down: Duration::from_secs(down),
+			end: start + Duration::from_secs(duration), steps, step: RAMP_MIN_STEP, chunks }
+	}
+
+	#[test]
+	fn ramp_steps_are_exact() {
+		let mib = MB as usize;
+		let ramp = ramp(100 * mib, 100, 0, 100, Some(4));
+		let at = |secs| ramp.target_at(ramp.start + Duration::from_secs(secs));
+		assert_eq!(at(0), 0);
+		assert_eq!(at(24), 0);
+		assert_eq!(at(25), 25 * mib);
+		assert_eq!(at(50), 50 * mib);
+		assert_eq!(at(99), 75 * mib);
+		assert_eq!(at(100), 100 * mib);
+	}
+
+	#[test]
+	fn ramp_steps_down() {
+		let mib = MB as usize;
+		let ramp = ramp(100 * mib, 50, 40, 100, Some(2));
+		let at = |secs| ramp.target_at(ramp.start + Duration::from_secs(secs));
+		assert_eq!(at(25), 50 * mib);
+		assert_eq!(at(60), 100 * mib);
+		// half of the ramp down left
+		assert_eq!(at(80), 50 * mib);
+		assert_eq!(at(100), 0);
+	}
+
+	#[test]
+	fn ramp_linear() {
+		let mib = MB as usize;
+		let ramp = ramp(100 * mib, 100, 0, 100, None);
+		let at = |secs| ramp.target_at(ramp.start + Duration::from_secs(secs));
+		assert_eq!(at(10), 10 * mib);
+		assert_eq!(at(100), 100 * mib);
+	}
+
+	#[test]
+	fn parse_rate_per_time_unit() {
+		assert_eq!(parse_rate("10M/s").unwrap(), 10_000_000.0);
+		assert_eq!(parse_rate("10MiB/s").unwrap(), 10.0 * 1024.0 * 1024.0);
+		assert_eq!(parse_rate("1G/h").unwrap(), 1_000_000_000.0 / 3600.0);
+		assert_eq!(parse_rate("60K/m").unwrap(), 1000.0);
+	}
+
+	#[test]
+	fn parse_rate_per_duration() {
+		assert_eq!(parse_rate("10M/5s").unwrap(), 2_000_000.0);
+		assert_eq!(parse_rate("1G/2h").unwrap(), 1_000_000_000.0 / 7200.0);
+	}
+
+	#[test]
+	fn parse_rate_invalid() {
+		assert!(parse_rate("10M").is_err());
+		assert!(parse_rate("10M/0s").is_err());
+		assert!(parse_rate("0M/s").is_err());
+		assert!(parse_rate("10M/x").is_err());
+		assert!(parse_rate("ten/s").is_err());
+	}
+}
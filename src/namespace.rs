@@ -0,0 +1,19 @@
+use std::fs::File;
+
+use nix::sched::{setns, CloneFlags};
+
+// processes forked afterwards are born into the pid namespace of the target, memfill itself stays where it is
+pub fn enter_pid_namespace(pid: u32) -> Result<(), String> {
+	enter(pid, "pid", CloneFlags::CLONE_NEWPID)
+}
+
+// requires a single threaded process, i.e. a freshly forked child
+pub fn enter_mount_namespace(pid: u32) -> Result<(), String> {
+	enter(pid, "mnt", CloneFlags::CLONE_NEWNS)
+}
+
+fn enter(pid: u32, namespace: &str, flag: CloneFlags) -> Result<(), String> {
+	let path = format!("/proc/{}/ns/{}", pid, namespace);
+	let file = File::open(&path).map_err(|e| format!("{}: {}", path, e))?;
+	setns(file, flag).map_err(|e| format!("setns({}) failed: {}", path, e))
+}
@@ -0,0 +1,98 @@
+use std::ffi::c_ulong;
+use std::fs;
+
+use nix::errno::Errno;
+use nix::libc;
+
+#[derive(Debug, Clone)]
+pub struct NodeSet(pub Vec<usize>);
+
+// accepts node lists like numactl: 0 / 0,2 / 0-3
+pub fn parse_node_set(input: impl AsRef<str>) -> Result<NodeSet, String> {
+	let mut nodes = vec![];
+	for part in input.as_ref().split(',') {
+		match part.split_once('-') {
+			Some((from, to)) => {
+				let from: usize = from.parse().map_err(|e| format!("{}", e))?;
+				let to: usize = to.parse().map_err(|e| format!("{}", e))?;
+				nodes.extend(from..=to);
+			}
+			None => nodes.push(part.parse().map_err(|e| format!("{}", e))?),
+		}
+	}
+	if nodes.is_empty() {
+		return Err("no numa node given".to_string());
+	}
+	Ok(NodeSet(nodes))
+}
+
+#[derive(Debug, Clone)]
+pub enum NumaPolicy {
+	Bind(NodeSet),
+	Interleave(NodeSet),
+}
+
+impl NumaPolicy {
+	pub fn nodes(&self) -> &NodeSet {
+		match self {
+			NumaPolicy::Bind(nodes) | NumaPolicy::Interleave(nodes) => nodes,
+		}
+	}
+
+	fn mode(&self) -> libc::c_long {
+		match self {
+			NumaPolicy::Bind(_) => libc::MPOL_BIND as libc::c_long,
+			NumaPolicy::Interleave(_) => libc::MPOL_INTERLEAVE as libc::c_long,
+		}
+	}
+
+	fn mask(&self) -> Vec<c_ulong> {
+		let nodes = &self.nodes().0;
+		let bits = c_ulong::BITS as usize;
+		let mut mask = vec![0 as c_ulong; nodes.iter().max().unwrap_or(&0) / bits + 1];
+		for node in nodes {
+			mask[node / bits] |= 1 << (node % bits);
+		}
+		mask
+	}
+
+	// applies the policy to a memory range, must happen before the pages are faulted in
+	pub unsafe fn bind(&self, ptr: *mut u8, len: usize) -> Result<(), String> {
+		let mask = self.mask();
+		let maxnode = (mask.len() * c_ulong::BITS as usize + 1) as c_ulong;
+		let res = libc::syscall(libc::SYS_mbind, ptr, len, self.mode(), mask.as_ptr(), maxnode, 0 as libc::c_uint);
+		Errno::result(res).map(drop).map_err(|e| format!("mbind failed: {}", e))
+	}
+
+	// applies the policy to all future allocations of the process and its children
+	pub fn apply(&self) -> Result<(), String> {
+		let mask = self.mask();
+		let maxnode = (mask.len() * c_ulong::BITS as usize + 1) as c_ulong;
+		let res = unsafe { libc::syscall(libc::SYS_set_mempolicy, self.mode(), mask.as_ptr(), maxnode) };
+		Errno::result(res).map(drop).map_err(|e| format!("set_mempolicy failed: {}", e))
+	}
+}
+
+pub struct NodeMemInfo {
+	pub total: usize,
+	// free memory plus what is easily reclaimable, there is no MemAvailable per node
+	pub available: usize,
+}
+
+pub fn read_node_meminfo(node: usize) -> Result<NodeMemInfo, String> {
+	let path = format!("/sys/devices/system/node/node{}/meminfo", node);
+	let content = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path, e))?;
+	let mut info = NodeMemInfo { total: 0, available: 0 };
+	// Node 0 MemTotal:        6147400 kB
+	for line in content.lines() {
+		let mut fields = line.split_whitespace().skip(2);
+		let (Some(key), Some(value)) = (fields.next(), fields.next()) else { continue };
+		let bytes = value.parse::<usize>().map_err(|e| format!("{}: {}", path, e))? * 1024;
+		match key {
+			"MemTotal:" => info.total = bytes,
+			"MemFree:" | "Inactive(file):" | "SReclaimable:" => info.available += bytes,
+			_ => {}
+		}
+	}
+	Ok(info)
+}
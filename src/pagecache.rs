@@ -0,0 +1,141 @@
+use std::fs::{self, File, OpenOptions};
+use std::collections::HashMap;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use nix::fcntl::{posix_fadvise, PosixFadviseAdvice};
+use procfs::{Current, Meminfo};
+use strum_macros::EnumString;
+
+use crate::page_size;
+use crate::pattern::{FillPattern, Filler};
+
+const BLOCK_SIZE: usize = 1024 * 1024;
+
+static FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(EnumString, Debug, Clone, Copy)]
+pub enum Fadvise {
+	#[strum(serialize = "normal")]
+	Normal,
+	#[strum(serialize = "sequential")]
+	Sequential,
+	#[strum(serialize = "random")]
+	Random,
+	#[strum(serialize = "noreuse")]
+	NoReuse,
+	#[strum(serialize = "willneed")]
+	WillNeed,
+	#[strum(serialize = "dontneed")]
+	DontNeed,
+}
+
+impl From<Fadvise> for PosixFadviseAdvice {
+	fn from(value: Fadvise) -> Self {
+		match value {
+			Fadvise::Normal => PosixFadviseAdvice::POSIX_FADV_NORMAL,
+			Fadvise::Sequential => PosixFadviseAdvice::POSIX_FADV_SEQUENTIAL,
+			Fadvise::Random => PosixFadviseAdvice::POSIX_FADV_RANDOM,
+			Fadvise::NoReuse => PosixFadviseAdvice::POSIX_FADV_NOREUSE,
+			Fadvise::WillNeed => PosixFadviseAdvice::POSIX_FADV_WILLNEED,
+			Fadvise::DontNeed => PosixFadviseAdvice::POSIX_FADV_DONTNEED,
+		}
+	}
+}
+
+pub fn new_file_path(dir: &Path) -> PathBuf {
+	dir.join(format!("memfill-{}-{}", process::id(), FILE_COUNTER.fetch_add(1, Ordering::Relaxed)))
+}
+
+// writes a file of the given size, flushes it to disk and reads it back, so it ends up as clean page cache.
+// the advice applies to the read back, dontneed drops the written pages instead
+pub fn fill(path: &Path, size: usize, pattern: FillPattern, advice: Option<Fadvise>) -> Result<(), String> {
+	let mut file = OpenOptions::new().create_new(true).write(true).open(path)
+		.map_err(|e| format!("{}: {}", path.display(), e))?;
+	write_file(&mut file, size, pattern).and_then(|_| {
+		file.sync_data()?;
+		if let Some(Fadvise::DontNeed) = advice {
+			posix_fadvise(file.as_raw_fd(), 0, size as i64, PosixFadviseAdvice::POSIX_FADV_DONTNEED)?;
+			return Ok(());
+		}
+		let reader = File::open(path)?;
+		if let Some(advice) = advice {
+			posix_fadvise(reader.as_raw_fd(), 0, size as i64, advice.into())?;
+		}
+		read_file(reader)
+	}).map_err(|e| {
+		let _ = fs::remove_file(path);
+		format!("{}: {}", path.display(), e)
+	})
+}
+
+pub fn write_file(file: &mut File, size: usize, pattern: FillPattern) -> std::io::Result<()> {
+	let mut filler = Filler::new(pattern, page_size());
+	let mut block = vec![0u8; BLOCK_SIZE];
+	let mut written = 0;
+	while written < size {
+		let len = BLOCK_SIZE.min(size - written);
+		filler.fill(&mut block[..len]);
+		file.write_all(&block[..len])?;
+		written += len;
+	}
+	Ok(())
+}
+
+// writes a file of the given size without syncing it, its pages stay dirty until the kernel writes them back
+pub fn fill_dirty(path: &Path, size: usize, pattern: FillPattern) -> Result<File, String> {
+	let mut file = OpenOptions::new().create_new(true).write(true).open(path)
+		.map_err(|e| format!("{}: {}", path.display(), e))?;
+	write_file(&mut file, size, pattern).map_err(|e| {
+		let _ = fs::remove_file(path);
+		format!("{}: {}", path.display(), e)
+	})?;
+	Ok(file)
+}
+
+// overwrites part of the file, dirtying the pages again that have been written back in the meantime
+pub fn redirty(file: &mut File, offset: usize, len: usize, pattern: FillPattern) -> std::io::Result<()> {
+	file.seek(SeekFrom::Start(offset as u64))?;
+	write_file(file, len, pattern)
+}
+
+fn read_file(mut file: File) -> std::io::Result<()> {
+	let mut block = vec![0u8; BLOCK_SIZE];
+	while file.read(&mut block)? > 0 {}
+	Ok(())
+}
+
+// drops the cached pages of the file and removes it unless it should be kept
+pub fn release(path: &Path, keep: bool) -> Result<(), String> {
+	if keep {
+		let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+		posix_fadvise(file.as_raw_fd(), 0, 0, PosixFadviseAdvice::POSIX_FADV_DONTNEED).map_err(|e| format!("{}: {}", path.display(), e))?;
+		Ok(())
+	} else {
+		fs::remove_file(path).map_err(|e| format!("{}: {}", path.display(), e))
+	}
+}
+
+pub struct DirtyMemory {
+	pub dirty: usize,
+	pub writeback: usize,
+	// only when memory is computed for a cgroup
+	pub cgroup_dirty: Option<usize>,
+	pub cgroup_writeback: Option<usize>,
+}
+
+impl DirtyMemory {
+	pub fn read(stat: Option<&HashMap<String, usize>>) -> Result<Self, String> {
+		let meminfo = Meminfo::current().map_err(|e| e.to_string())?;
+		let stat_value = |key: &str| stat.and_then(|s| s.get(key)).copied();
+		Ok(Self {
+			dirty: meminfo.dirty as usize,
+			writeback: meminfo.writeback as usize,
+			cgroup_dirty: stat_value("file_dirty"),
+			cgroup_writeback: stat_value("file_writeback"),
+		})
+	}
+}
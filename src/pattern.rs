@@ -0,0 +1,91 @@
+use rand::{RngCore, SeedableRng};
+use rand::rngs::SmallRng;
+
+#[derive(Debug, Clone, Copy)]
+pub enum FillPattern {
+	Constant,
+	Random,
+	Ratio(f64),
+	Identical,
+	Unique,
+}
+
+pub fn parse_fill_pattern(input: impl AsRef<str>) -> Result<FillPattern, String> {
+	let input = input.as_ref();
+	match input {
+		"constant" => Ok(FillPattern::Constant),
+		"random" => Ok(FillPattern::Random),
+		"identical" => Ok(FillPattern::Identical),
+		"unique" => Ok(FillPattern::Unique),
+		_ => {
+			let (original, compressed) = input.split_once(':').ok_or(format!("unknown pattern {}", input))?;
+			let original: f64 = original.parse().map_err(|e| format!("{}", e))?;
+			let compressed: f64 = compressed.parse().map_err(|e| format!("{}", e))?;
+			let ratio = original / compressed;
+			if ratio.is_nan() || ratio < 1.0 {
+				return Err("compression ratio must be at least 1:1".to_string());
+			}
+			Ok(FillPattern::Ratio(ratio))
+		}
+	}
+}
+
+pub struct Filler {
+	pattern: FillPattern,
+	page_size: usize,
+	byte: u8,
+	page: Vec<u8>,
+	nonce: u64,
+	pages: u64,
+	rng: SmallRng,
+}
+
+impl Filler {
+	pub fn new(pattern: FillPattern, page_size: usize) -> Self {
+		let mut rng = SmallRng::from_os_rng();
+		let mut page = vec![];
+		if let FillPattern::Identical = pattern {
+			page.resize(page_size, 0);
+			rng.fill_bytes(&mut page);
+		}
+		return Self { pattern, page_size, byte: rand::random(), page, nonce: rand::random(), pages: 0, rng };
+	}
+
+	pub fn fill(&mut self, buf: &mut [u8]) {
+		match self.pattern {
+			FillPattern::Constant => {
+				buf.fill(self.byte);
+				if buf.first().is_some_and(|b| *b != self.byte) {
+					panic!("Memory pattern assertion failed");
+				}
+			}
+			FillPattern::Random => self.rng.fill_bytes(buf),
+			FillPattern::Ratio(ratio) => {
+				// each page starts with random bytes followed by zeros, compressing to about 1/ratio
+				let random = (self.page_size as f64 / ratio) as usize;
+				for page in buf.chunks_mut(self.page_size) {
+					let split = random.min(page.len());
+					self.rng.fill_bytes(&mut page[..split]);
+					page[split..].fill(0);
+				}
+			}
+			FillPattern::Identical => {
+				// the same random page over and over, each page incompressible on its own but mergeable by ksm
+				for page in buf.chunks_mut(self.page_size) {
+					let len = page.len();
+					page.copy_from_slice(&self.page[..len]);
+				}
+			}
+			FillPattern::Unique => {
+				// constant bytes, but each page is stamped with a nonce and its index so ksm can't merge them
+				for page in buf.chunks_mut(self.page_size) {
+					page.fill(self.byte);
+					let stamp = [self.nonce.to_le_bytes(), self.pages.to_le_bytes()].concat();
+					let len = stamp.len().min(page.len());
+					page[..len].copy_from_slice(&stamp[..len]);
+					self.pages += 1;
+				}
+			}
+		}
+	}
+}
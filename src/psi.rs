@@ -0,0 +1,83 @@
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+use strum_macros::{Display, EnumString};
+
+#[derive(EnumString, Deserialize, Display, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PsiMetric {
+	#[default]
+	#[strum(serialize = "some")]
+	Some,
+	#[strum(serialize = "full")]
+	Full,
+}
+
+#[derive(Debug)]
+pub struct MemoryPressure {
+	pub some_avg10: f64,
+	pub full_avg10: f64,
+}
+
+impl MemoryPressure {
+	pub fn avg10(&self, metric: PsiMetric) -> f64 {
+		match metric {
+			PsiMetric::Some => self.some_avg10,
+			PsiMetric::Full => self.full_avg10,
+		}
+	}
+}
+
+pub fn read_memory_pressure<P: AsRef<Path>>(path: P) -> Result<MemoryPressure, String> {
+	let path = path.as_ref();
+	let content = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+	parse_memory_pressure(&content).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+// some avg10=0.00 avg60=0.00 avg300=0.00 total=0
+// full avg10=0.00 avg60=0.00 avg300=0.00 total=0
+fn parse_memory_pressure(content: &str) -> Result<MemoryPressure, String> {
+	let mut pressure = MemoryPressure { some_avg10: 0.0, full_avg10: 0.0 };
+	for line in content.lines() {
+		let mut fields = line.split_whitespace();
+		let kind = fields.next();
+		let avg10 = fields
+			.find_map(|f| f.strip_prefix("avg10="))
+			.ok_or(format!("missing avg10 in '{}'", line))?
+			.parse()
+			.map_err(|e| format!("{}", e))?;
+		match kind {
+			Some("some") => pressure.some_avg10 = avg10,
+			Some("full") => pressure.full_avg10 = avg10,
+			_ => {}
+		}
+	}
+	Ok(pressure)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parse_some_and_full() {
+		let pressure = parse_memory_pressure("some avg10=1.50 avg60=0.80 avg300=0.20 total=12345\nfull avg10=0.25 avg60=0.10 avg300=0.00 total=678\n").unwrap();
+		assert_eq!(pressure.avg10(PsiMetric::Some), 1.5);
+		assert_eq!(pressure.avg10(PsiMetric::Full), 0.25);
+	}
+
+	#[test]
+	fn parse_some_only() {
+		// a missing full line leaves it at zero
+		let pressure = parse_memory_pressure("some avg10=3.00 avg60=0.00 avg300=0.00 total=0").unwrap();
+		assert_eq!(pressure.some_avg10, 3.0);
+		assert_eq!(pressure.full_avg10, 0.0);
+	}
+
+	#[test]
+	fn parse_invalid() {
+		assert!(parse_memory_pressure("some avg60=0.00").is_err());
+		assert!(parse_memory_pressure("some avg10=x").is_err());
+	}
+}
@@ -0,0 +1,95 @@
+use std::ffi::c_void;
+use std::num::NonZeroUsize;
+use std::ptr::NonNull;
+
+use nix::errno::Errno;
+use nix::sys::mman::{madvise, mlock, mmap_anonymous, MapFlags, MmapAdvise, munmap, ProtFlags};
+use nix::sys::resource::{getrlimit, Resource, RLIM_INFINITY};
+use strum_macros::EnumString;
+
+const HUGE_2M: usize = 2 * 1024 * 1024;
+const HUGE_1G: usize = 1024 * 1024 * 1024;
+
+#[derive(EnumString, Debug, Clone, Copy, PartialEq)]
+pub enum PageBacking {
+	#[strum(serialize = "normal")]
+	Normal,
+	#[strum(serialize = "thp")]
+	Thp,
+	#[strum(serialize = "hugetlb-2m")]
+	Hugetlb2M,
+	#[strum(serialize = "hugetlb-1g")]
+	Hugetlb1G,
+}
+
+impl PageBacking {
+	// hugetlb pages are allocated whole
+	pub fn huge_page_size(&self) -> Option<usize> {
+		match self {
+			PageBacking::Normal | PageBacking::Thp => None,
+			PageBacking::Hugetlb2M => Some(HUGE_2M),
+			PageBacking::Hugetlb1G => Some(HUGE_1G),
+		}
+	}
+}
+
+// anonymous memory mapping, unmapped on drop
+pub struct Region {
+	ptr: NonNull<c_void>,
+	len: usize,
+}
+
+impl Region {
+	pub fn new(size: usize, backing: PageBacking) -> Result<Self, String> {
+		let len = backing.huge_page_size().map_or(size, |page| size.next_multiple_of(page));
+		let flags = match backing {
+			PageBacking::Normal | PageBacking::Thp => MapFlags::empty(),
+			PageBacking::Hugetlb2M => MapFlags::MAP_HUGETLB | MapFlags::MAP_HUGE_2MB,
+			PageBacking::Hugetlb1G => MapFlags::MAP_HUGETLB | MapFlags::MAP_HUGE_1GB,
+		};
+		let length = NonZeroUsize::new(len).ok_or("size must not be zero")?;
+		let ptr = unsafe { mmap_anonymous(None, length, ProtFlags::PROT_READ | ProtFlags::PROT_WRITE, MapFlags::MAP_PRIVATE | flags) }
+			.map_err(|e| format!("mmap ({:?}) failed: {}", backing, e))?;
+		let region = Self { ptr, len };
+		if backing == PageBacking::Thp {
+			region.advise(MmapAdvise::MADV_HUGEPAGE)?;
+		}
+		Ok(region)
+	}
+
+	pub fn as_ptr(&self) -> *mut u8 {
+		self.ptr.as_ptr() as *mut u8
+	}
+
+	pub fn advise(&self, advice: MmapAdvise) -> Result<(), String> {
+		unsafe { madvise(self.ptr, self.len, advice) }.map_err(|e| format!("madvise({:?}) failed: {}", advice, e))
+	}
+
+	pub fn advise_range(&self, offset: usize, len: usize, advice: MmapAdvise) -> Result<(), String> {
+		let ptr = unsafe { self.ptr.byte_add(offset) };
+		unsafe { madvise(ptr, len, advice) }.map_err(|e| format!("madvise({:?}) failed: {}", advice, e))
+	}
+
+	// keeps the pages resident, they can neither be swapped out nor reclaimed
+	pub fn lock(&self) -> Result<(), String> {
+		unsafe { mlock(self.ptr, self.len) }.map_err(|e| match e {
+			Errno::EPERM => format!("mlock failed: {}; requires CAP_IPC_LOCK", e),
+			Errno::ENOMEM | Errno::EAGAIN => format!("mlock failed: {}; RLIMIT_MEMLOCK is {}, requires CAP_IPC_LOCK or a higher limit", e, memlock_limit()),
+			_ => format!("mlock failed: {}", e),
+		})
+	}
+}
+
+fn memlock_limit() -> String {
+	match getrlimit(Resource::RLIMIT_MEMLOCK) {
+		Ok((soft, _)) if soft == RLIM_INFINITY => "unlimited".to_string(),
+		Ok((soft, _)) => crate::bytes_to_string_usize(soft as usize),
+		Err(e) => format!("unknown ({})", e),
+	}
+}
+
+impl Drop for Region {
+	fn drop(&mut self) {
+		let _ = unsafe { munmap(self.ptr, self.len) };
+	}
+}
@@ -0,0 +1,158 @@
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use duration_str::parse as parse_duration;
+use serde::{Deserialize, Deserializer};
+use serde::de::Error;
+
+use crate::{AllocationMode, ModeOpt, parse_rate, parse_size, Size};
+
+#[derive(Deserialize, Debug)]
+pub struct Scenario {
+	pub phases: Vec<Phase>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct Phase {
+	#[serde(deserialize_with = "size")]
+	pub size: Size,
+
+	pub mode: AllocationMode,
+
+	#[serde(deserialize_with = "duration")]
+	pub duration: Duration,
+
+	#[serde(default)]
+	pub release: bool,
+
+	#[serde(flatten)]
+	pub mode_opt: ModeOpt,
+}
+
+pub fn read_scenario<P: AsRef<Path>>(path: P) -> Result<Scenario, String> {
+	let path = path.as_ref();
+	let content = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+	parse_scenario(&content, path.extension().and_then(|e| e.to_str())).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+fn parse_scenario(content: &str, extension: Option<&str>) -> Result<Scenario, String> {
+	let scenario: Scenario = match extension {
+		Some("toml") => toml::from_str(content).map_err(|e| e.to_string())?,
+		// json is a subset of yaml
+		_ => serde_yaml::from_str(content).map_err(|e| e.to_string())?,
+	};
+	for (i, phase) in scenario.phases.iter().enumerate() {
+		if let Some(key) = phase.mode_opt.unknown.keys().min() {
+			return Err(format!("phase {}: unknown field `{}`", i + 1, key));
+		}
+	}
+	if scenario.phases.is_empty() {
+		return Err("no phases defined".to_string());
+	}
+	return Ok(scenario);
+}
+
+pub fn size<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Size, D::Error> {
+	parse_size(String::deserialize(deserializer)?).map_err(D::Error::custom)
+}
+
+pub fn opt_size<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Size>, D::Error> {
+	size(deserializer).map(Some)
+}
+
+pub fn duration<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+	parse_duration(String::deserialize(deserializer)?).map_err(D::Error::custom)
+}
+
+pub fn opt_duration<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+	duration(deserializer).map(Some)
+}
+
+pub fn opt_rate<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<f64>, D::Error> {
+	parse_rate(String::deserialize(deserializer)?).map(Some).map_err(D::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const YAML: &str = "
+phases:
+  - size: 2G
+    mode: ramp
+    duration: 10m
+    ramp-up: 5m
+  - size: 50%
+    mode: sine
+    duration: 1h
+    low: 1G
+    period: 10m
+    release: true
+";
+
+	fn assert_phases(scenario: &Scenario) {
+		assert_eq!(scenario.phases.len(), 2);
+		let (ramp, sine) = (&scenario.phases[0], &scenario.phases[1]);
+		assert!(matches!(ramp.size, Size::Bytes(2_000_000_000)));
+		assert!(matches!(ramp.mode, AllocationMode::Ramp));
+		assert_eq!(ramp.duration, Duration::from_secs(600));
+		assert_eq!(ramp.mode_opt.ramp.ramp_up, Some(Duration::from_secs(300)));
+		assert!(!ramp.release);
+		assert!(matches!(sine.size, Size::Percent(50)));
+		assert!(matches!(sine.mode, AllocationMode::Sine));
+		assert!(matches!(sine.mode_opt.wave.low, Some(Size::Bytes(1_000_000_000))));
+		assert_eq!(sine.mode_opt.wave.period, Duration::from_secs(600));
+		assert!(sine.release);
+	}
+
+	#[test]
+	fn parse_yaml() {
+		assert_phases(&parse_scenario(YAML, Some("yaml")).unwrap());
+		assert_phases(&parse_scenario(YAML, None).unwrap());
+	}
+
+	#[test]
+	fn parse_json() {
+		let json = r#"{"phases": [
+			{"size": "2G", "mode": "ramp", "duration": "10m", "ramp-up": "5m"},
+			{"size": "50%", "mode": "sine", "duration": "1h", "low": "1G", "period": "10m", "release": true}
+		]}"#;
+		assert_phases(&parse_scenario(json, Some("json")).unwrap());
+	}
+
+	#[test]
+	fn parse_toml() {
+		let toml = r#"
+[[phases]]
+size = "2G"
+mode = "ramp"
+duration = "10m"
+ramp-up = "5m"
+
+[[phases]]
+size = "50%"
+mode = "sine"
+duration = "1h"
+low = "1G"
+period = "10m"
+release = true
+"#;
+		assert_phases(&parse_scenario(toml, Some("toml")).unwrap());
+	}
+
+	#[test]
+	fn reject_unknown_keys() {
+		let yaml = YAML.replace("ramp-up", "rampup");
+		assert_eq!(parse_scenario(&yaml, None).err().unwrap(), "phase 1: unknown field `rampup`");
+	}
+
+	#[test]
+	fn reject_invalid_phases() {
+		assert_eq!(parse_scenario("phases: []", None).err().unwrap(), "no phases defined");
+		assert!(parse_scenario("phases:\n  - size: 2G\n    mode: ramp\n", None).is_err());
+		assert!(parse_scenario("phases:\n  - size: lots\n    mode: ramp\n    duration: 1m\n", None).is_err());
+		assert!(parse_scenario("phases:\n  - size: 2G\n    mode: climb\n    duration: 1m\n", None).is_err());
+	}
+}
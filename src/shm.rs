@@ -0,0 +1,28 @@
+use std::ffi::CString;
+use std::fs::{self, File, OpenOptions};
+use std::os::fd::OwnedFd;
+use std::path::Path;
+
+use nix::sys::memfd::{memfd_create, MemFdCreateFlag};
+
+use crate::pagecache::write_file;
+use crate::pattern::FillPattern;
+
+// files on tmpfs are shmem, they stay in memory (or swap) until removed
+pub fn fill_file(path: &Path, size: usize, pattern: FillPattern) -> Result<(), String> {
+	let mut file = OpenOptions::new().create_new(true).write(true).open(path)
+		.map_err(|e| format!("{}: {}", path.display(), e))?;
+	write_file(&mut file, size, pattern).map_err(|e| {
+		let _ = fs::remove_file(path);
+		format!("{}: {}", path.display(), e)
+	})
+}
+
+// the memory is released once the returned fd is closed
+pub fn fill_memfd(size: usize, pattern: FillPattern) -> Result<OwnedFd, String> {
+	let name = CString::new("memfill").unwrap();
+	let fd = memfd_create(&name, MemFdCreateFlag::MFD_CLOEXEC).map_err(|e| format!("memfd_create: {}", e))?;
+	let mut file = File::from(fd);
+	write_file(&mut file, size, pattern).map_err(|e| format!("memfd: {}", e))?;
+	Ok(file.into())
+}
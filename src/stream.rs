@@ -0,0 +1,83 @@
+use std::ptr::{read_volatile, write_volatile};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+const BLOCK_SIZE: usize = 1024 * 1024;
+
+// raw pointers are not Send, the region outlives the threads as they are joined in stop()
+#[derive(Clone, Copy)]
+struct Region(*mut u8, usize);
+
+unsafe impl Send for Region {}
+
+pub struct Streams {
+	threads: Vec<JoinHandle<()>>,
+	stop: Arc<AtomicBool>,
+	bytes: Arc<AtomicU64>,
+	last_report: (Instant, u64),
+}
+
+impl Streams {
+	// continuously reads and writes back the region with the given number of threads,
+	// each thread limited to its share of the rate in bytes per second
+	pub fn start(ptr: *mut u8, size: usize, threads: usize, rate: Option<f64>) -> Self {
+		let stop = Arc::new(AtomicBool::new(false));
+		let bytes = Arc::new(AtomicU64::new(0));
+		let slice = (size / threads.max(1)) & !7;
+		let handles = (0..threads).filter(|_| slice > 0).map(|i| {
+			let region = Region(unsafe { ptr.add(i * slice) }, slice);
+			let stop = stop.clone();
+			let bytes = bytes.clone();
+			let rate = rate.map(|r| r / threads as f64);
+			thread::spawn(move || stream(region, rate, &stop, &bytes))
+		}).collect();
+		return Self { threads: handles, stop, bytes, last_report: (Instant::now(), 0) };
+	}
+
+	// bytes per second streamed since the last call
+	pub fn throughput(&mut self) -> u64 {
+		let now = Instant::now();
+		let bytes = self.bytes.load(Ordering::Relaxed);
+		let (last, last_bytes) = self.last_report;
+		self.last_report = (now, bytes);
+		let elapsed = (now - last).as_secs_f64();
+		if elapsed > 0.0 { ((bytes - last_bytes) as f64 / elapsed) as u64 } else { 0 }
+	}
+
+	pub fn stop(self) {
+		self.stop.store(true, Ordering::Relaxed);
+		for t in self.threads {
+			let _ = t.join();
+		}
+	}
+}
+
+fn stream(region: Region, rate: Option<f64>, stop: &AtomicBool, bytes: &AtomicU64) {
+	let Region(ptr, size) = region;
+	let start = Instant::now();
+	let mut streamed = 0u64;
+	let mut offset = 0;
+	while !stop.load(Ordering::Relaxed) {
+		let len = BLOCK_SIZE.min(size - offset);
+		unsafe {
+			let block = ptr.add(offset) as *mut u64;
+			for i in 0..len / 8 {
+				write_volatile(block.add(i), read_volatile(block.add(i)));
+			}
+		}
+		offset = if offset + len >= size { 0 } else { offset + len };
+		// every byte is read and written
+		streamed += 2 * len as u64;
+		bytes.fetch_add(2 * len as u64, Ordering::Relaxed);
+
+		if let Some(rate) = rate {
+			let due = Duration::from_secs_f64(streamed as f64 / rate);
+			let elapsed = start.elapsed();
+			if due > elapsed {
+				thread::sleep(due - elapsed);
+			}
+		}
+	}
+}
@@ -0,0 +1,96 @@
+use std::ptr::{read_volatile, write_volatile};
+use std::time::Duration;
+
+use rand::{Rng, SeedableRng};
+use rand::rngs::SmallRng;
+
+use crate::{stream, ChunkOpt};
+
+#[derive(Debug, Clone, Copy)]
+pub enum TouchPattern {
+	Sequential,
+	Random,
+	Hot(u8),
+}
+
+pub fn parse_touch_pattern(input: impl AsRef<str>) -> Result<TouchPattern, String> {
+	let input = input.as_ref();
+	match input {
+		"sequential" => Ok(TouchPattern::Sequential),
+		"random" => Ok(TouchPattern::Random),
+		_ => {
+			let percent = input.strip_prefix("hot:").ok_or(format!("unknown touch pattern {}", input))?;
+			let percent: u8 = percent.trim_end_matches('%').parse().map_err(|e| format!("{}", e))?;
+			if percent > 100 {
+				return Err("hot percentage must not exceed 100".to_string());
+			}
+			Ok(TouchPattern::Hot(percent))
+		}
+	}
+}
+
+pub struct Toucher {
+	pattern: TouchPattern,
+	page_size: usize,
+	rng: SmallRng,
+}
+
+impl Toucher {
+	pub fn new(pattern: TouchPattern, page_size: usize) -> Self {
+		return Self { pattern, page_size, rng: SmallRng::from_os_rng() };
+	}
+
+	// touches the pages of the region once according to the pattern
+	pub unsafe fn touch(&mut self, ptr: *mut u8, size: usize) {
+		let pages = size.div_ceil(self.page_size);
+		match self.pattern {
+			TouchPattern::Sequential => {
+				for page in 0..pages {
+					touch_page(ptr.add(page * self.page_size));
+				}
+			}
+			TouchPattern::Random => {
+				for _ in 0..pages {
+					touch_page(ptr.add(self.rng.random_range(0..pages) * self.page_size));
+				}
+			}
+			TouchPattern::Hot(percent) => {
+				let hot = pages * percent as usize / 100;
+				for page in 0..hot {
+					touch_page(ptr.add(page * self.page_size));
+				}
+			}
+		}
+	}
+}
+
+unsafe fn touch_page(ptr: *mut u8) {
+	// read and write back to mark the page accessed and dirty
+	write_volatile(ptr, read_volatile(ptr));
+}
+
+// keeps touching and streaming over the filled region of a chunk until it is stopped.
+// wait sleeps for the interval or, with nothing to do, until woken up; report receives the streamed bytes per second
+pub unsafe fn keep_active(ptr: *mut u8, size: usize, opt: &ChunkOpt, stopped: impl Fn() -> bool, mut wait: impl FnMut(Option<Duration>), mut report: impl FnMut(u64)) {
+	let mut streams = match (opt.bandwidth, opt.bandwidth_threads) {
+		(None, None) => None,
+		(rate, threads) => Some(stream::Streams::start(ptr, size, threads.unwrap_or(1), rate)),
+	};
+	let mut toucher = opt.touch.map(|pattern| Toucher::new(pattern, crate::page_size()));
+	while !stopped() {
+		if let Some(toucher) = toucher.as_mut() {
+			toucher.touch(ptr, size);
+		}
+		wait(match (&toucher, &streams) {
+			(Some(_), _) => Some(opt.touch_interval),
+			(None, Some(_)) => Some(Duration::from_secs(1)),
+			(None, None) => None,
+		});
+		if let Some(streams) = streams.as_mut() {
+			report(streams.throughput());
+		}
+	}
+	if let Some(streams) = streams.take() {
+		streams.stop();
+	}
+}
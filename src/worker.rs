@@ -0,0 +1,78 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+
+use crate::{touch, Chunk, ChunkOpt};
+
+static WORKER_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+// a thread owning the memory of a chunk, the alternative to a forked child process
+pub struct Worker {
+	name: String,
+	thread: JoinHandle<()>,
+	stop: Arc<AtomicBool>,
+	bandwidth: Arc<AtomicU64>,
+}
+
+impl Worker {
+	// returns once the worker has filled its memory
+	pub fn start(size: usize, opt: &ChunkOpt) -> Result<Self, String> {
+		let name = format!("memfill-{}", WORKER_COUNTER.fetch_add(1, Ordering::Relaxed));
+		let stop = Arc::new(AtomicBool::new(false));
+		let bandwidth = Arc::new(AtomicU64::new(0));
+		let (ready, filled) = mpsc::channel();
+		let thread = {
+			let (opt, stop, bandwidth) = (opt.clone(), stop.clone(), bandwidth.clone());
+			thread::Builder::new().name(name.clone())
+				.spawn(move || work(size, &opt, ready, &stop, &bandwidth))
+				.map_err(|e| format!("failed to spawn thread: {}", e))?
+		};
+		match filled.recv() {
+			Ok(Ok(())) => Ok(Self { name, thread, stop, bandwidth }),
+			Ok(Err(e)) => {
+				let _ = thread.join();
+				Err(e)
+			}
+			Err(_) => {
+				let _ = thread.join();
+				Err("thread exited before it allocated the memory".to_string())
+			}
+		}
+	}
+
+	pub fn name(&self) -> &str {
+		&self.name
+	}
+
+	// bytes per second streamed over the memory
+	pub fn bandwidth(&self) -> u64 {
+		self.bandwidth.load(Ordering::Relaxed)
+	}
+
+	// the memory is freed when the thread returns
+	pub fn stop(self) {
+		self.stop.store(true, Ordering::SeqCst);
+		self.thread.thread().unpark();
+		let _ = self.thread.join();
+	}
+}
+
+fn work(size: usize, opt: &ChunkOpt, ready: mpsc::Sender<Result<(), String>>, stop: &AtomicBool, bandwidth: &AtomicU64) {
+	let region = match Chunk::fill_region(size, opt) {
+		Ok(region) => region,
+		Err(e) => {
+			let _ = ready.send(Err(e));
+			return;
+		}
+	};
+	let _ = ready.send(Ok(()));
+
+	// stop() unparks the thread
+	unsafe {
+		touch::keep_active(region.as_ptr(), size, opt, || stop.load(Ordering::SeqCst), |interval| match interval {
+			Some(interval) => thread::park_timeout(interval),
+			None => thread::park(),
+		}, |throughput| bandwidth.store(throughput, Ordering::Relaxed));
+	}
+}